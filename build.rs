@@ -1,7 +1,11 @@
+#[cfg(windows)]
 extern crate winres;
 
 fn main() {
-    let mut res = winres::WindowsResource::new();
-    res.set_icon("icon.ico");
-    res.compile().expect("Failed to compile Windows resource");
+    #[cfg(windows)]
+    {
+        let mut res = winres::WindowsResource::new();
+        res.set_icon("icon.ico");
+        res.compile().expect("Failed to compile Windows resource");
+    }
 }
\ No newline at end of file
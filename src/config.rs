@@ -2,11 +2,51 @@ use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 
+use crate::minecraft::microsoft;
+
+/// Session tokens for an account on a server. Passwords are only sent once, when logging in.
+/// The tokens themselves live in the secret store and are filled in once it's opened.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct LauncherCredentials {
     pub uuid: String,
     pub username: String,
-    pub password: String
+    #[serde(skip)]
+    pub tokens: SessionTokens,
+    /// Unix time in seconds after which the access token has to be refreshed.
+    #[serde(default)]
+    pub expires_at: u64,
+    /// Tokens of configs written before the secret store existed, moved there on first open.
+    #[serde(rename = "access_token", default, skip_serializing_if = "Option::is_none")]
+    pub plaintext_access_token: Option<String>,
+    #[serde(rename = "refresh_token", default, skip_serializing_if = "Option::is_none")]
+    pub plaintext_refresh_token: Option<String>,
+    /// Password of configs written before sessions existed, traded for tokens on first load and never written back.
+    #[serde(default, skip_serializing)]
+    pub password: Option<String>
+}
+
+impl LauncherCredentials {
+    pub fn new(uuid: String, username: String, tokens: SessionTokens, expires_at: u64) -> Self {
+        Self { uuid, username, tokens, expires_at, plaintext_access_token: None, plaintext_refresh_token: None, password: None }
+    }
+}
+
+/// For Yggdrasil servers `refresh_token` holds the client token the access token is bound to.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SessionTokens {
+    pub access_token: String,
+    pub refresh_token: String
+}
+
+/// Session protocol a server authenticates its players with.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthBackend {
+    /// XCraftAuth's own `/api/` endpoints on `session_server_port`.
+    #[default]
+    XCraft,
+    /// Yggdrasil (authlib-injector style) API under `api_root`, e.g. `https://example.com/api/yggdrasil`.
+    Yggdrasil { api_root: String }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -14,9 +54,42 @@ pub struct LauncherServer {
     pub domain: String, 
     pub port: u16,
     pub session_server_port: u16,
+    #[serde(default)]
+    pub auth: AuthBackend,
     pub credentials: LauncherCredentials
 }
 
+impl LauncherServer {
+    /// Secret store key of the account.
+    pub fn account(&self) -> String {
+        [self.credentials.username.as_str(), "@", &self.domain].concat()
+    }
+
+    /// Address in the `domain:port#session_port` form the add server form takes.
+    pub fn address(&self) -> String {
+        match self.auth {
+            AuthBackend::XCraft => [self.domain.as_str(), ":", &self.port.to_string(), "#", &self.session_server_port.to_string()].concat(),
+            AuthBackend::Yggdrasil { .. } => [self.domain.as_str(), ":", &self.port.to_string()].concat()
+        }
+    }
+}
+
+/// Per-instance overrides stored in `instance.toml` next to `client.json`.
+#[derive(Serialize, Deserialize, Default)]
+pub struct InstanceSettings {
+    pub java_path: Option<String>,
+    pub min_memory: Option<u32>,
+    pub max_memory: Option<u32>,
+    #[serde(default)]
+    pub jvm_args: Vec<String>,
+    #[serde(default)]
+    pub game_args: Vec<String>,
+    pub window_width: Option<u32>,
+    pub window_height: Option<u32>,
+    #[serde(default)]
+    pub fullscreen: bool
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct LauncherConfig {
     is_portable: bool,
@@ -28,12 +101,35 @@ pub struct LauncherConfig {
     pub ram_amount: u32,
     pub enable_blur: bool,
     pub allow_http: bool,
-    servers: Vec<LauncherServer>
+    #[serde(default = "default_download_threads")]
+    pub download_threads: usize,
+    #[serde(default)]
+    pub window_width: Option<u32>,
+    #[serde(default)]
+    pub window_height: Option<u32>,
+    /// CurseForge API root, overridable to point modpack imports at a mirror or a local stub.
+    #[serde(default = "default_curseforge_api_url")]
+    pub curseforge_api_url: String,
+    #[serde(default)]
+    pub curseforge_api_key: Option<String>,
+    servers: Vec<LauncherServer>,
+    #[serde(default)]
+    microsoft_accounts: Vec<LauncherCredentials>,
+    #[serde(default)]
+    pub microsoft: microsoft::Endpoints
+}
+
+fn default_download_threads() -> usize {
+    8
+}
+
+fn default_curseforge_api_url() -> String {
+    "https://api.curseforge.com".to_string()
 }
 
 impl Default for LauncherConfig {
     fn default() -> Self {
-        Self { is_portable: Default::default(), user_name: Default::default(), java_path: "javaw".to_string(), show_alpha: true, show_beta: true, show_snapshots: false, ram_amount: 1024, servers: Default::default(), enable_blur: Default::default(), allow_http: Default::default() }
+        Self { is_portable: Default::default(), user_name: Default::default(), java_path: if cfg!(windows) { "javaw" } else { "java" }.to_string(), show_alpha: true, show_beta: true, show_snapshots: false, ram_amount: 1024, servers: Default::default(), enable_blur: Default::default(), allow_http: Default::default(), download_threads: default_download_threads(), window_width: None, window_height: None, curseforge_api_url: default_curseforge_api_url(), curseforge_api_key: None, microsoft_accounts: Default::default(), microsoft: Default::default() }
     }
 }
 
@@ -67,6 +163,36 @@ impl LauncherConfig {
         p
     }
 
+    pub fn runtimes_path(&self) -> PathBuf {
+        let mut p = self.launcher_dir();
+        p.push("runtimes");
+        p
+    }
+
+    pub fn server_icons_path(&self) -> PathBuf {
+        let mut p = self.launcher_dir();
+        p.push("server_icons");
+        p
+    }
+
+    /// Deleted instances end up here instead of being removed outright.
+    pub fn trash_path(&self) -> PathBuf {
+        let mut p = self.launcher_dir();
+        p.push("trash");
+        p
+    }
+
+    /// Encrypted secret store of portable installs.
+    pub fn credentials_path(&self) -> PathBuf {
+        let mut p = self.launcher_dir();
+        p.push("credentials.bin");
+        p
+    }
+
+    pub fn is_portable(&self) -> bool {
+        self.is_portable
+    }
+
     pub fn config_path(&self) -> PathBuf {
         let mut p = self.launcher_dir();
         p.push("config.toml");
@@ -85,9 +211,45 @@ impl LauncherConfig {
         &self.servers
     }
 
+    /// Adds a server, replacing the entry of the same account if it was logged in before.
     pub fn add_server(&mut self, server: LauncherServer) {
-        self.servers.push(server);
+        match self.servers.iter_mut().find(|s| s.domain == server.domain && s.credentials.username == server.credentials.username) {
+            Some(existing) => *existing = server,
+            None => self.servers.push(server)
+        }
     }
+
+    pub fn microsoft_accounts(&self) -> &Vec<LauncherCredentials> {
+        &self.microsoft_accounts
+    }
+
+    /// Adds a Microsoft account, replacing the one with the same profile if it was signed in before.
+    pub fn add_microsoft_account(&mut self, account: LauncherCredentials) {
+        match self.microsoft_accounts.iter_mut().find(|a| a.uuid == account.uuid) {
+            Some(existing) => *existing = account,
+            None => self.microsoft_accounts.push(account)
+        }
+    }
+
+    pub fn find_microsoft_account_mut(&mut self, uuid: &str) -> Option<&mut LauncherCredentials> {
+        self.microsoft_accounts.iter_mut().find(|a| a.uuid == uuid)
+    }
+
+    /// Credentials of every server account and Microsoft account, with their secret store keys.
+    pub fn credentials_mut(&mut self) -> impl Iterator<Item = (String, &mut LauncherCredentials)> {
+        self.servers.iter_mut()
+            .map(|s| (s.account(), &mut s.credentials))
+            .chain(self.microsoft_accounts.iter_mut().map(|a| (microsoft_account_key(&a.uuid), a)))
+    }
+
+    pub fn find_server_mut(&mut self, username: &str, domain: &str) -> Option<&mut LauncherServer> {
+        self.servers.iter_mut().find(|s| s.domain == domain && s.credentials.username == username)
+    }
+}
+
+/// Secret store key of a Microsoft account.
+pub fn microsoft_account_key(uuid: &str) -> String {
+    ["microsoft:", uuid].concat()
 }
 
 pub fn get_relative_launcher_dir() -> PathBuf {
@@ -1,7 +1,9 @@
 use core::str;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::io::Cursor;
-use std::path::PathBuf;
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use base64::Engine;
 use base64::prelude::BASE64_STANDARD;
 use rand::rngs::StdRng;
@@ -9,21 +11,25 @@ use rand::seq::IndexedRandom;
 use rand::SeedableRng;
 use serde::{Deserialize, Serialize};
 use surf::StatusCode;
-use tokio::fs::File;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::Command;
-use tokio::sync::mpsc;
 use tokio::sync::mpsc::UnboundedSender;
-use crate::config::{LauncherCredentials, LauncherServer};
+use crate::config::{microsoft_account_key, AuthBackend, InstanceSettings, LauncherCredentials, LauncherServer, SessionTokens};
 use crate::minecraft;
+use crate::secrets::{EncryptedFileStore, KeyringStore, SecretStore};
 use crate::minecraft::multimc::Pack;
-use crate::minecraft::session::SignUpResponse;
-use crate::minecraft::versions::Version;
+use crate::minecraft::session::{Session, SignUpResponse};
+use crate::minecraft::yggdrasil::AuthResult;
+use crate::minecraft::versions::{Version, VersionLibrary};
 use crate::{config::LauncherConfig, minecraft::versions::VersionConfig, util};
 use ureq_multipart::MultipartBuilder;
 
-const JAVA_ARGS: [&str; 22] = ["-Xms512M", 
-"-XX:+UnlockExperimentalVMOptions", 
+const DEFAULT_MIN_MEMORY: u32 = 512;
+const SERVER_ICON_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Access tokens this close to expiring (in seconds) get refreshed before use.
+const SESSION_REFRESH_MARGIN: u64 = 60;
+
+const JAVA_ARGS: [&str; 21] = ["-XX:+UnlockExperimentalVMOptions", 
 "-XX:+DisableExplicitGC",
 "-XX:MaxGCPauseMillis=200",
 "-XX:+AlwaysPreTouch",
@@ -40,9 +46,14 @@ const JAVA_ARGS: [&str; 22] = ["-Xms512M",
 "-XX:G1RSetUpdatingPauseTimePercent=5",
 "-XX:+UseStringDeduplication", "-Dfile.encoding=UTF-8", "-Dfml.ignoreInvalidMinecraftCertificates=true", "-Dfml.ignorePatchDiscrepancies=true", "-Djava.net.useSystemProxies=true", "-XX:HeapDumpPath=MojangTricksIntelDriversForPerformance_javaw.exe_minecraft.exe.heapdump"];
 
+const CLASSPATH_SEPARATOR: &str = if cfg!(windows) { ";" } else { ":" };
+
 #[derive(Default)]
 pub struct Launcher {
     pub config: LauncherConfig,
+    secrets: Option<Box<dyn SecretStore>>,
+    /// Device code and its expiry time of the Microsoft sign-in in progress.
+    microsoft_login: Option<(String, u64)>,
 }
 
 impl Launcher {
@@ -55,31 +66,34 @@ impl Launcher {
         self.config.config_path().exists()
     }
 
-    pub fn load_config(&mut self) {
+    pub async fn load_config(&mut self) {
         if self.is_config_exist() {
             self.config = toml::from_str(
                 str::from_utf8(&std::fs::read(self.config.config_path()).unwrap()).unwrap()).unwrap();
         }
+        self.open_secrets();
+        self.migrate_passwords().await;
     }
 
     pub fn save_config(&self) {
         let _ = std::fs::write(self.config.config_path(), toml::to_string_pretty(&self.config).unwrap());
     }
 
-    pub fn init_config(&mut self, user_name: String) {
-        self.load_config();
+    pub async fn init_config(&mut self, user_name: String) {
+        self.load_config().await;
         self.config.set_username(user_name);
         self.save_config();
     }
 
-    pub async fn upload_skin(&self, file_path: PathBuf, uuid: &str, password: &str, server_url: &str) -> Result<String, Box<dyn Error + Sync + Send>> {
+    pub async fn upload_skin(&self, file_path: PathBuf, uuid: &str, access_token: &str, server_url: &str) -> Result<String, Box<dyn Error + Sync + Send>> {
         let (content_type,data) = MultipartBuilder::new()
             .add_file("skin",file_path)?
             .finish()?;
 
         let mut resp = ureq::post(server_url)
                     .content_type(content_type)
-                    .query_pairs(vec![("uuid", uuid), ("password", password)])
+                    .header("Authorization", &["Bearer ", access_token].concat())
+                    .query_pairs(vec![("uuid", uuid)])
                     .send(data)?;
 
         let s = resp.body_mut().read_to_string()?;
@@ -87,14 +101,15 @@ impl Launcher {
         Ok(s)
     }
 
-    pub async fn upload_cape(&self, file_path: PathBuf, uuid: &str, password: &str, server_url: &str) -> Result<String, Box<dyn Error + Sync + Send>> {
+    pub async fn upload_cape(&self, file_path: PathBuf, uuid: &str, access_token: &str, server_url: &str) -> Result<String, Box<dyn Error + Sync + Send>> {
         let (content_type,data) = MultipartBuilder::new()
             .add_file("cape",file_path)?
             .finish()?;
 
         let mut resp = ureq::post(server_url)
                     .content_type(content_type)
-                    .query_pairs(vec![("uuid", uuid), ("password", password)])
+                    .header("Authorization", &["Bearer ", access_token].concat())
+                    .query_pairs(vec![("uuid", uuid)])
                     .send(data)?;
 
         let s = resp.body_mut().read_to_string()?;
@@ -102,9 +117,10 @@ impl Launcher {
         Ok(s)
     }
 
-    pub async fn set_skin_model(&self, is_slim: bool, uuid: &str, password: &str, server_url: &str) -> Result<String, Box<dyn Error + Sync + Send>> {
+    pub async fn set_skin_model(&self, is_slim: bool, uuid: &str, access_token: &str, server_url: &str) -> Result<String, Box<dyn Error + Sync + Send>> {
         let mut resp = ureq::post(server_url)
-                    .query_pairs(vec![("uuid", uuid), ("password", password), ("model", &is_slim.to_string())])
+                    .header("Authorization", &["Bearer ", access_token].concat())
+                    .query_pairs(vec![("uuid", uuid), ("model", &is_slim.to_string())])
                     .send_empty()?;
 
         let s = resp.body_mut().read_to_string()?;
@@ -112,18 +128,14 @@ impl Launcher {
         Ok(s)
     }
 
-    fn save_server_info(&mut self, uuid: String, username: String, password: String, domain: String, session_server_port: u16, server_port: u16) -> (bool, &str) {
-        self.config.add_server(LauncherServer {
-            domain,
-            port: server_port,
-            session_server_port,
-            credentials: LauncherCredentials {
-                uuid,
-                username,
-                password
-            }
-        });
+    fn save_server_info(&mut self, server: LauncherServer) -> (bool, &str) {
+        let (username, domain) = (server.credentials.username.clone(), server.domain.clone());
+        self.config.add_server(server);
         self.save_config();
+        if let Err(e) = self.store_tokens(&username, &domain) {
+            println!("Error: {}", e);
+            return (false, "Could not store credentials");
+        }
         (true, "You are successfully registered")
     }
 
@@ -146,12 +158,12 @@ impl Launcher {
 
         println!("Server information: {}:{} session={}", domain, server_port, session_server_port);
 
-        match minecraft::session::try_signup(domain.clone(), session_server_port, username.clone(), password.clone(), self.config.allow_http).await {
+        match minecraft::session::try_signup(domain.clone(), session_server_port, username.clone(), password, self.config.allow_http).await {
             Ok(status) => match status {
                 SignUpResponse::ServerError => (false, "Internal server error"),
                 SignUpResponse::BadCredentials => (false, "Username or password is not valid"),
                 SignUpResponse::UserAlreadyExists => (false, "User already exists"),
-                SignUpResponse::Registered(uuid) => self.save_server_info(uuid, username, password, domain, session_server_port, server_port)
+                SignUpResponse::Registered(session) => self.save_server_info(xcraft_server(session, username, domain, session_server_port, server_port))
             }
             Err(_e) => (false, "Internal server error")
         }
@@ -176,26 +188,320 @@ impl Launcher {
 
         println!("Server information: {}:{} session={}", domain, server_port, session_server_port);
 
-        match minecraft::session::try_login(domain.clone(), session_server_port, username.clone(), password.clone(), self.config.allow_http).await {
+        match minecraft::session::try_login(domain.clone(), session_server_port, username.clone(), password, self.config.allow_http).await {
             Ok(status) => match status {
                 SignUpResponse::ServerError => (false, "Internal server error"),
                 SignUpResponse::BadCredentials => (false, "Username or password is not valid"),
                 SignUpResponse::UserAlreadyExists => (false, "User already exists"),
-                SignUpResponse::Registered(uuid) => self.save_server_info(uuid, username, password, domain, session_server_port, server_port)
+                SignUpResponse::Registered(session) => self.save_server_info(xcraft_server(session, username, domain, session_server_port, server_port))
             }
             Err(_e) => (false, "Internal server error")
         }
     }
 
-    pub async fn get_servers_list(&self) -> Vec<(String, String, Option<String>)> {
-        let mut v = Vec::new();
+    /// Logs into a Yggdrasil server at `server` (`domain[:port]`) through its API at `api_root`,
+    /// picking the profile named `username` when the account has several.
+    pub async fn login_yggdrasil_server(&mut self, server: String, api_root: String, username: String, password: String) -> (bool, &str) {
+        let (domain, server_port) = match server.split_once(':') {
+            Some((domain, port)) => match port.parse() {
+                Ok(port) => (domain.to_string(), port),
+                Err(_) => return (false, "Server port is not valid")
+            },
+            None => (server, 25565)
+        };
+
+        println!("Server information: {}:{} yggdrasil={}", domain, server_port, api_root);
+
+        let client_token = util::random_string(32);
+        let auth = match minecraft::yggdrasil::authenticate(&api_root, &username, &password, &client_token).await {
+            Ok(AuthResult::Authenticated(auth)) => auth,
+            Ok(AuthResult::Rejected(msg)) => {
+                println!("Error: {}", msg);
+                return (false, "Username or password is not valid");
+            }
+            Err(e) => {
+                println!("Error: {}", e);
+                return (false, "Could not reach the auth server");
+            }
+        };
+
+        let auth = if auth.selected_profile.is_some() {
+            auth
+        } else {
+            let Some(profile) = auth.available_profiles.iter().find(|p| p.name.eq_ignore_ascii_case(&username)).or(auth.available_profiles.first()) else {
+                return (false, "This account has no Minecraft profile");
+            };
+            match minecraft::yggdrasil::refresh(&api_root, &auth.access_token, &auth.client_token, Some(profile)).await {
+                Ok(Some(auth)) => auth,
+                _ => return (false, "Could not select a profile")
+            }
+        };
+        let Some(profile) = auth.selected_profile else {
+            return (false, "This account has no Minecraft profile");
+        };
+
+        if let Some(old) = self.find_credentials(&profile.name, &domain).filter(|s| s.auth == AuthBackend::Yggdrasil { api_root: api_root.clone() }) {
+            let _ = minecraft::yggdrasil::invalidate(&api_root, &old.credentials.tokens.access_token, &old.credentials.tokens.refresh_token).await;
+        }
+
+        self.save_server_info(LauncherServer {
+            domain,
+            port: server_port,
+            session_server_port: 0,
+            auth: AuthBackend::Yggdrasil { api_root },
+            credentials: LauncherCredentials::new(profile.id, profile.name, SessionTokens { access_token: auth.access_token, refresh_token: auth.client_token }, 0)
+        })
+    }
+
+    /// Pings every server at once, each attempt bounded by the ping's own timeout, and returns their icons next to
+    /// the statuses. Servers that don't answer keep the icon cached from an earlier ping, as long as it isn't
+    /// older than `SERVER_ICON_TTL`; servers that answer without one lose it.
+    pub async fn get_servers_list(&self) -> Vec<(String, String, Option<String>, Option<minecraft::server::ServerStatus>)> {
         let servers = self.config.servers();
-        for server in servers {
-            v.push((server.domain.clone(), server.credentials.username.clone(), minecraft::server::get_server_icon(&server.domain, server.port).await.unwrap_or(None)));
+        let statuses = futures::future::join_all(servers.iter().map(|server| async move {
+            minecraft::server::ping(&server.domain, server.port).await.ok()
+        })).await;
+
+        let mut v = Vec::new();
+        for (server, status) in servers.iter().zip(statuses) {
+            let icon_path = self.server_icon_path(&server.domain, server.port);
+            let icon = match status.as_ref().map(|s| s.favicon.as_deref()) {
+                Some(Some(favicon)) => {
+                    if let Some(png) = minecraft::server::decode_favicon(favicon) {
+                        let _ = std::fs::create_dir_all(self.config.server_icons_path());
+                        let _ = std::fs::write(&icon_path, png);
+                    }
+                    Some(favicon.to_string())
+                }
+                Some(None) => {
+                    let _ = std::fs::remove_file(&icon_path);
+                    None
+                }
+                None => self.cached_server_icon(&icon_path)
+            };
+            v.push((server.domain.clone(), server.credentials.username.clone(), icon, status));
         }
         v
     }
 
+    fn server_icon_path(&self, domain: &str, port: u16) -> PathBuf {
+        let name = domain.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' }).collect::<String>();
+        let mut p = self.config.server_icons_path();
+        p.push(format!("{}_{}.png", name, port));
+        p
+    }
+
+    fn cached_server_icon(&self, icon_path: &Path) -> Option<String> {
+        let modified = std::fs::metadata(icon_path).and_then(|m| m.modified()).ok()?;
+        if modified.elapsed().unwrap_or_default() > SERVER_ICON_TTL {
+            return None;
+        }
+        let png = std::fs::read(icon_path).ok()?;
+        Some(minecraft::server::favicon_url(&png))
+    }
+
+    /// Makes sure the stored access token is still valid, refreshing it when it's about to expire.
+    /// Fails when the refresh token was rejected too, in which case the user has to log in again.
+    pub async fn refresh_session(&mut self, username: &str, domain: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let allow_http = self.config.allow_http;
+        let server = self.config.find_server_mut(username, domain).ok_or("Unknown server account")?;
+        if let AuthBackend::Yggdrasil { api_root } = &server.auth {
+            let tokens = &server.credentials.tokens;
+            if tokens.access_token.is_empty() {
+                return Err("Session expired, please log in again".into());
+            }
+            if minecraft::yggdrasil::validate(api_root, &tokens.access_token, &tokens.refresh_token).await? {
+                return Ok(());
+            }
+            let auth = minecraft::yggdrasil::refresh(api_root, &tokens.access_token, &tokens.refresh_token, None).await?;
+            server.credentials.tokens = match auth {
+                Some(auth) => SessionTokens { access_token: auth.access_token, refresh_token: auth.client_token },
+                None => SessionTokens::default()
+            };
+            let expired = server.credentials.tokens.access_token.is_empty();
+            self.store_tokens(username, domain)?;
+            return if expired { Err("Session expired, please log in again".into()) } else { Ok(()) };
+        }
+        let tokens = &mut server.credentials.tokens;
+        if !tokens.access_token.is_empty() && util::unix_time() + SESSION_REFRESH_MARGIN < server.credentials.expires_at {
+            return Ok(());
+        }
+        if tokens.refresh_token.is_empty() {
+            return Err("Session expired, please log in again".into());
+        }
+        let session = minecraft::session::refresh(&server.domain, server.session_server_port, &tokens.refresh_token, allow_http).await?;
+        let Some(session) = session else {
+            server.credentials.tokens = SessionTokens::default();
+            self.store_tokens(username, domain)?;
+            return Err("Session expired, please log in again".into());
+        };
+        server.credentials.uuid = session.uuid;
+        server.credentials.tokens = SessionTokens { access_token: session.access_token, refresh_token: session.refresh_token };
+        server.credentials.expires_at = util::unix_time() + session.expires_in;
+        self.save_config();
+        self.store_tokens(username, domain)
+    }
+
+    /// Opens the platform keyring for regular installs. Portable installs, and desktops without a
+    /// reachable secret service, stay locked until `unlock_secrets` is given the passphrase.
+    fn open_secrets(&mut self) {
+        if self.secrets.is_some() || self.config.is_portable() {
+            return;
+        }
+        match KeyringStore::open() {
+            Ok(store) => {
+                self.secrets = Some(Box::new(store));
+                self.load_secrets();
+            }
+            Err(e) => println!("Error: {}", e)
+        }
+    }
+
+    pub fn secrets_locked(&self) -> bool {
+        self.secrets.is_none()
+    }
+
+    /// Opens the passphrase-encrypted secret store, creating it on first use.
+    pub fn unlock_secrets(&mut self, passphrase: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.secrets = Some(Box::new(EncryptedFileStore::open(self.config.credentials_path(), passphrase)?));
+        self.load_secrets();
+        Ok(())
+    }
+
+    /// Fills in the tokens of every account from the secret store, moving the plaintext ones
+    /// of older configs into it along the way.
+    fn load_secrets(&mut self) {
+        let Some(secrets) = self.secrets.as_mut() else { return };
+        let mut migrated = false;
+        for (account, credentials) in self.config.credentials_mut() {
+            if credentials.plaintext_access_token.is_some() || credentials.plaintext_refresh_token.is_some() {
+                credentials.tokens = SessionTokens {
+                    access_token: credentials.plaintext_access_token.clone().unwrap_or_default(),
+                    refresh_token: credentials.plaintext_refresh_token.clone().unwrap_or_default()
+                };
+                match secrets.set(&account, &serde_json::to_string(&credentials.tokens).unwrap()) {
+                    Ok(()) => {
+                        credentials.plaintext_access_token = None;
+                        credentials.plaintext_refresh_token = None;
+                        migrated = true;
+                    }
+                    Err(e) => println!("Error: {}", e)
+                }
+                continue;
+            }
+            match secrets.get(&account) {
+                Ok(Some(secret)) => credentials.tokens = serde_json::from_str(&secret).unwrap_or_default(),
+                // Tokens from a login that happened while the store was still locked
+                Ok(None) if !credentials.tokens.access_token.is_empty() => {
+                    if let Err(e) = secrets.set(&account, &serde_json::to_string(&credentials.tokens).unwrap()) {
+                        println!("Error: {}", e);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => println!("Error: {}", e)
+            }
+        }
+        if migrated {
+            self.save_config();
+        }
+    }
+
+    /// Logs in once with the passwords older configs kept in plaintext, then drops them from the config.
+    /// Accounts whose login fails are left without a session and get asked to log in again.
+    async fn migrate_passwords(&mut self) {
+        let logins: Vec<(String, String, u16, u16, String)> = self.config.servers().iter()
+            .filter_map(|s| Some((s.credentials.username.clone(), s.domain.clone(), s.session_server_port, s.port, s.credentials.password.clone()?)))
+            .collect();
+        if logins.is_empty() {
+            return;
+        }
+        for (username, domain, session_server_port, server_port, password) in logins {
+            match minecraft::session::try_login(domain.clone(), session_server_port, username.clone(), password, self.config.allow_http).await {
+                Ok(SignUpResponse::Registered(session)) => {
+                    self.config.add_server(xcraft_server(session, username.clone(), domain.clone(), session_server_port, server_port));
+                    if !self.secrets_locked() && let Err(e) = self.store_tokens(&username, &domain) {
+                        println!("Error: {}", e);
+                    }
+                }
+                Ok(_) => println!("Error: saved password of {} on {} was not accepted", username, domain),
+                Err(e) => println!("Error: {}", e)
+            }
+        }
+        for (_, credentials) in self.config.credentials_mut() {
+            credentials.password = None;
+        }
+        self.save_config();
+    }
+
+    fn store_tokens(&mut self, username: &str, domain: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let secrets = self.secrets.as_mut().ok_or("Credentials are locked")?;
+        let server = self.config.servers().iter().find(|s| s.domain == domain && s.credentials.username == username).ok_or("Unknown server account")?;
+        secrets.set(&server.account(), &serde_json::to_string(&server.credentials.tokens)?)
+    }
+
+    /// Starts a Microsoft sign-in, returning the code the user has to enter, the URL to enter it at
+    /// and how many seconds to wait between polls.
+    pub async fn start_microsoft_login(&mut self) -> Result<(String, String, u64), Box<dyn Error + Send + Sync>> {
+        let code = minecraft::microsoft::request_device_code(&self.config.microsoft).await?;
+        let prompt = (code.user_code.clone(), code.verification_uri.clone(), code.interval);
+        self.microsoft_login = Some((code.device_code, util::unix_time() + code.expires_in));
+        Ok(prompt)
+    }
+
+    /// Checks once on the sign-in started by `start_microsoft_login`. Returns the profile name once it's done.
+    pub async fn poll_microsoft_login(&mut self) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        let (device_code, expires_at) = self.microsoft_login.as_ref().ok_or("No Microsoft sign in in progress")?;
+        if util::unix_time() > *expires_at {
+            self.microsoft_login = None;
+            return Err("The code has expired, please sign in again".into());
+        }
+        let account = match minecraft::microsoft::poll_device_code(&self.config.microsoft, device_code).await {
+            Ok(Some(account)) => account,
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                self.microsoft_login = None;
+                return Err(e);
+            }
+        };
+        self.microsoft_login = None;
+
+        let (uuid, username) = (account.uuid.clone(), account.username.clone());
+        self.config.add_microsoft_account(microsoft_credentials(account));
+        self.save_config();
+        self.store_microsoft_tokens(&uuid)?;
+        Ok(Some(username))
+    }
+
+    /// Signs a Microsoft account in again once its Minecraft token is about to expire.
+    pub async fn refresh_microsoft_account(&mut self, uuid: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let account = self.config.microsoft_accounts().iter().find(|a| a.uuid == uuid).ok_or("Unknown Microsoft account")?;
+        if !account.tokens.access_token.is_empty() && util::unix_time() + SESSION_REFRESH_MARGIN < account.expires_at {
+            return Ok(());
+        }
+        if account.tokens.refresh_token.is_empty() {
+            return Err("Session expired, please sign in again".into());
+        }
+        let refreshed = minecraft::microsoft::refresh(&self.config.microsoft, &account.tokens.refresh_token).await?;
+        let account = self.config.find_microsoft_account_mut(uuid).ok_or("Unknown Microsoft account")?;
+        match refreshed {
+            Some(refreshed) => *account = microsoft_credentials(refreshed),
+            None => account.tokens = SessionTokens::default()
+        }
+        let expired = account.tokens.access_token.is_empty();
+        self.save_config();
+        self.store_microsoft_tokens(uuid)?;
+        if expired {
+            return Err("Session expired, please sign in again".into());
+        }
+        Ok(())
+    }
+
+    fn store_microsoft_tokens(&mut self, uuid: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let secrets = self.secrets.as_mut().ok_or("Credentials are locked")?;
+        let account = self.config.microsoft_accounts().iter().find(|a| a.uuid == uuid).ok_or("Unknown Microsoft account")?;
+        secrets.set(&microsoft_account_key(uuid), &serde_json::to_string(&account.tokens)?)
+    }
+
     pub fn find_credentials(&self, username: &str, domain: &str) -> Option<&LauncherServer> {
         let servers = self.config.servers();
         servers.iter().find(|&server| server.domain == domain && server.credentials.username == username)
@@ -227,6 +533,184 @@ impl Launcher {
         v
     }
 
+    /// Directory of an existing instance. Names that aren't a single plain path component are rejected.
+    fn instance_dir(&self, instance_name: &str) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+        let name = util::safe_relative_path(instance_name).filter(|p| p.components().count() == 1).ok_or(format!("Invalid instance name {}", instance_name))?;
+        Ok(self.config.instances_path().join(name))
+    }
+
+    /// Directory for a new instance, which must not exist yet.
+    fn new_instance_dir(&self, instance_name: &str) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+        let dir = self.instance_dir(instance_name)?;
+        if dir.exists() {
+            return Err(format!("Instance {} already exists", instance_name).into());
+        }
+        Ok(dir)
+    }
+
+    pub fn rename_instance(&self, instance_name: &str, new_name: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let from = self.instance_dir(instance_name)?;
+        let to = self.new_instance_dir(new_name)?;
+        std::fs::rename(from, to)?;
+        Ok(())
+    }
+
+    /// Copies an instance, leaving out its game directory (worlds, mods, options) unless `with_data` is set.
+    pub fn duplicate_instance(&self, instance_name: &str, new_name: &str, with_data: bool) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let from = self.instance_dir(instance_name)?;
+        let to = self.new_instance_dir(new_name)?;
+        std::fs::create_dir_all(&to)?;
+        for entry in std::fs::read_dir(&from)? {
+            let entry = entry?;
+            let target = to.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                if entry.file_name() == "data" && !with_data {
+                    std::fs::create_dir_all(target)?;
+                    continue;
+                }
+                util::copy_dir_all(&entry.path(), &target)?;
+            } else {
+                std::fs::copy(entry.path(), target)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves an instance to the trash folder, under a random suffix so repeated deletes don't collide.
+    pub fn delete_instance(&self, instance_name: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let from = self.instance_dir(instance_name)?;
+        let mut trash = self.config.trash_path();
+        std::fs::create_dir_all(&trash)?;
+        trash.push([instance_name, "_", &util::random_string(4)].concat());
+        std::fs::rename(from, trash)?;
+        Ok(())
+    }
+
+    /// Re-checks `client.jar`, libraries, natives, assets and the java runtime against `client.json`
+    /// and downloads whatever is missing or corrupt.
+    pub async fn repair_instance(&self, instance_name: &str, sender: UnboundedSender<(u8, String)>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let instance_dir = self.instance_dir(instance_name)?;
+        let config: VersionConfig = serde_json::from_slice(&std::fs::read(instance_dir.join("client.json"))?)?;
+
+        let mut queue = util::DownloadQueue::new(self.config.download_threads);
+        if let Some(downloads) = &config.downloads {
+            let client = &downloads.client;
+            queue.push_if_invalid(&client.url, instance_dir.join("client.jar"), Some(&client.sha1), Some(client.size), "Downloading client.jar");
+        }
+        self.queue_libraries(&config.libraries, &mut queue);
+        if config.asset_index.is_some() {
+            self.queue_assets(&config, &mut queue).await?;
+        }
+        if let Err(e) = self.queue_runtime(&config, &mut queue).await {
+            println!("Error: {}", e);
+        }
+
+        spawn_downloads(queue, sender, None);
+        Ok(())
+    }
+
+    /// Finds files under the shared libraries and assets folders that no instance's `client.json` or asset index uses,
+    /// like libraries of deleted instances, nicotine `-patch.jar`s and leftovers of interrupted loader installs.
+    pub fn find_orphans(&self) -> Result<OrphanReport, Box<dyn Error + Send + Sync>> {
+        let libraries_dir = self.config.libraries_path();
+        let assets_dir = self.config.assets_path();
+        let mut referenced = HashSet::new();
+        let mut protected = Vec::new();
+        let mut assets_known = true;
+
+        for entry in std::fs::read_dir(self.config.instances_path())? {
+            let client_json = entry?.path().join("client.json");
+            let Ok(data) = std::fs::read(&client_json) else { continue; };
+            // Not knowing what an instance uses means not knowing what is safe to delete
+            let config: VersionConfig = serde_json::from_slice(&data).map_err(|e| format!("{}: {}", client_json.display(), e))?;
+
+            for library in &config.libraries {
+                referenced.insert(libraries_dir.join(library.to_pathbuf_file(false)));
+                if let Some(artifact) = &library.downloads.artifact {
+                    referenced.insert(libraries_dir.join(&artifact.path));
+                }
+                for natives in library.downloads.classifiers.iter().flat_map(|c| c.values()) {
+                    referenced.insert(libraries_dir.join(&natives.path));
+                }
+            }
+            // Module path entries of 1.17+ Forge live only in the JVM arguments
+            for argument in config.jvm_arguments(&[]).unwrap_or_default() {
+                for entry in argument.split("${classpath_separator}") {
+                    if let Some((_, relative)) = entry.split_once("${library_directory}/") {
+                        referenced.insert(libraries_dir.join(relative));
+                    }
+                }
+            }
+            // Forge processors write patched game jars here, and FML looks them up by itself
+            if matches!(config.loader(), Some(("forge" | "neoforge", _))) {
+                protected.push(libraries_dir.join("net").join("minecraft").join("client"));
+            }
+
+            if let Some(asset_index) = &config.asset_index {
+                let index_path = assets_dir.join(asset_index.to_path());
+                let assets = std::fs::read(&index_path).ok().and_then(|d| serde_json::from_slice::<minecraft::assets::Assets>(&d).ok());
+                referenced.insert(index_path);
+                match assets {
+                    Some(assets) => {
+                        referenced.extend(assets.objects.values().map(|asset| assets_dir.join(asset.to_path())));
+                        if assets.is_virtual {
+                            protected.push(assets_dir.join("virtual").join(&asset_index.id));
+                        }
+                    }
+                    None => assets_known = false
+                }
+            }
+        }
+
+        let mut candidates = util::list_files(&libraries_dir).unwrap_or_default();
+        if assets_known {
+            for dir in ["objects", "indexes", "virtual"] {
+                candidates.extend(util::list_files(&assets_dir.join(dir)).unwrap_or_default());
+            }
+        }
+        let files: Vec<PathBuf> = candidates.into_iter()
+            .filter(|f| !referenced.contains(f) && !protected.iter().any(|p| f.starts_with(p)))
+            .collect();
+        let bytes = files.iter().filter_map(|f| std::fs::metadata(f).ok()).map(|m| m.len()).sum();
+        Ok(OrphanReport { files, bytes })
+    }
+
+    /// Deletes what `find_orphans` reports, re-scanning first so nothing that became referenced meanwhile goes away.
+    pub fn delete_orphans(&self) -> Result<OrphanReport, Box<dyn Error + Send + Sync>> {
+        let report = self.find_orphans()?;
+        for file in &report.files {
+            if let Err(e) = std::fs::remove_file(file) {
+                println!("Failed to remove {}: {}", file.display(), e);
+            }
+        }
+        let _ = util::remove_empty_dirs(&self.config.libraries_path());
+        let _ = util::remove_empty_dirs(&self.config.assets_path());
+        Ok(report)
+    }
+
+    fn instance_settings_path(&self, instance_name: &str) -> PathBuf {
+        let mut p = self.config.instances_path();
+        p.push(instance_name);
+        p.push("instance.toml");
+        p
+    }
+
+    /// Settings of an instance, or the defaults if it has none. A broken `instance.toml` is logged and ignored.
+    pub fn load_instance_settings(&self, instance_name: &str) -> InstanceSettings {
+        let Ok(data) = std::fs::read_to_string(self.instance_settings_path(instance_name)) else {
+            return InstanceSettings::default();
+        };
+        toml::from_str(&data).unwrap_or_else(|e| {
+            println!("Error: {} has invalid settings, using defaults: {}", instance_name, e);
+            InstanceSettings::default()
+        })
+    }
+
+    pub fn save_instance_settings(&self, instance_name: &str, settings: &InstanceSettings) -> Result<(), Box<dyn Error + Send + Sync>> {
+        std::fs::write(self.instance_settings_path(instance_name), toml::to_string_pretty(settings)?)?;
+        Ok(())
+    }
+
     pub fn get_screenshots(&self) -> Vec<(String, String)> {
         let mut v = Vec::new();
         let instances = self.config.instances_path();
@@ -252,16 +736,22 @@ impl Launcher {
         v
     }
 
-    pub async fn launch_instance(&self, instance_name: String, sender: UnboundedSender<String>, special_server: Option<&LauncherServer> ) {
+    pub async fn launch_instance(&self, instance_name: String, sender: UnboundedSender<String>, special_server: Option<&LauncherServer>, microsoft_account: Option<&LauncherCredentials>) {
 
         let mut username = self.config.user_name();
-        let mut uuid = util::random_string(32);
+        let mut uuid = util::offline_uuid(username);
         let mut token = util::random_string(32);
-
-        if let Some(server) = special_server {
-            username = &server.credentials.username;
-            uuid = server.credentials.uuid.clone();
-            token = server.credentials.password.clone();
+        // Session server accounts behave like old Mojang accounts as far as authlib is concerned
+        let user_type = match (special_server, microsoft_account) {
+            (Some(_), _) => "mojang",
+            (None, Some(_)) => "msa",
+            (None, None) => "legacy"
+        };
+
+        if let Some(credentials) = special_server.map(|s| &s.credentials).or(microsoft_account) {
+            username = &credentials.username;
+            uuid = credentials.uuid.clone();
+            token = credentials.tokens.access_token.clone();
         }
         
         let mut instances = self.config.instances_path();
@@ -277,473 +767,755 @@ impl Launcher {
         instance_dir.push("data");
         let _ = std::fs::create_dir_all(&instance_dir);
 
-        let mut cmd = Command::new(&self.config.java_path);
+        let Ok(data) = std::fs::read(&instances) else { return; };
+        let config: VersionConfig = serde_json::from_slice(&data).unwrap();
+        let settings = self.load_instance_settings(&instance_name);
+
+        let java = match &settings.java_path {
+            Some(java_path) => PathBuf::from(java_path),
+            None => self.java_executable(&config)
+        };
+        let mut cmd = Command::new(java);
         cmd.current_dir(instance_dir);
         cmd.stdout(std::process::Stdio::piped());
         cmd.stderr(std::process::Stdio::piped());
 
-        
+        cmd.arg(["-Xms", &settings.min_memory.unwrap_or(DEFAULT_MIN_MEMORY).to_string(), "M"].concat());
         for arg in JAVA_ARGS {
             cmd.arg(arg);
         }
         
-        cmd.arg(["-Xmx", &self.config.ram_amount.to_string(), "M"].concat());
+        cmd.arg(["-Xmx", &settings.max_memory.unwrap_or(self.config.ram_amount).to_string(), "M"].concat());
+        cmd.args(&settings.jvm_args);
 
         let mut natives_path = self.config.instances_path();
         natives_path.push(&instance_name);
         natives_path.push("natives");
 
-        cmd.arg(["-Djava.library.path=", natives_path.to_str().unwrap() ].concat());
         cmd.arg(["-Dminecraft.client.jar=", client_jar.to_str().unwrap()].concat());
-        cmd.arg("-cp");
-
-        let mut minecraft_arguments = None;
-
-        if let Ok(data) = std::fs::read(&instances) {
-            let config: VersionConfig = serde_json::from_slice(&data).unwrap();
-            minecraft_arguments = Some(config.minecraft_arguments);
-            let mut libraries_cmd = Vec::new();
-            for library in config.libraries {
-                if let Some(classifier) = &library.downloads.classifiers {
-                    if let Some(natives) = &classifier.natives {
-                        let rel_path = &natives.path;
-                        let libs = self.config.libraries_path();
-                        let rel_path = [libs.to_str().unwrap(), "\\", &rel_path.replace("/", "\\")].concat();
-                        let data = std::fs::read(rel_path).unwrap();
-
-                        let _ = zip_extract::extract(Cursor::new(data), &natives_path, true);
-                    }
-                } else {
-                    let mut libs = self.config.libraries_path();
-                    libs.push(library.to_pathbuf_file(false));
-                    if library.name.contains("com.mojang:authlib") {
-                        if let Some(server) = special_server {
-                            let mut patched_auth = self.config.libraries_path();
-                            patched_auth.push(library.to_pathbuf_file(true));
-                            let _ = nicotine::patch_jar(libs.to_str().unwrap(), patched_auth.to_str().unwrap(), [b"https://sessionserver.mojang.com/session/minecraft/".as_slice(), b".minecraft.net".as_slice()].as_slice(),  &[&[if self.config.allow_http { "http://" } else { "https://" }, &server.domain, ":", &server.session_server_port.to_string(), "/api/"].concat(), &server.domain]);
-                            libraries_cmd.push([patched_auth.to_str().unwrap(), ";"].concat());
-                            continue;
-                        }
+
+        let mut libraries_cmd = Vec::new();
+        for library in config.libraries.iter().filter(|l| l.is_allowed()) {
+            if let Some(natives) = library.native_download() {
+                let mut natives_jar = self.config.libraries_path();
+                natives_jar.push(&natives.path);
+                if let Ok(data) = std::fs::read(natives_jar) {
+                    let _ = zip_extract::extract(Cursor::new(data), &natives_path, true);
+                }
+            }
+            if library.artifact_url().is_some() {
+                let mut libs = self.config.libraries_path();
+                libs.push(library.to_pathbuf_file(false));
+                if library.name.contains("com.mojang:authlib") {
+                    if let Some(server) = special_server {
+                        let mut patched_auth = self.config.libraries_path();
+                        patched_auth.push(library.to_pathbuf_file(true));
+                        let (session_url, texture_domain) = match &server.auth {
+                            AuthBackend::XCraft => ([if self.config.allow_http { "http://" } else { "https://" }, &server.domain, ":", &server.session_server_port.to_string(), "/api/"].concat(), server.domain.clone()),
+                            AuthBackend::Yggdrasil { api_root } => (minecraft::yggdrasil::session_url(api_root), surf::Url::parse(api_root).ok().and_then(|u| u.host_str().map(str::to_string)).unwrap_or_else(|| server.domain.clone()))
+                        };
+                        let _ = nicotine::patch_jar(libs.to_str().unwrap(), patched_auth.to_str().unwrap(), [b"https://sessionserver.mojang.com/session/minecraft/".as_slice(), b".minecraft.net".as_slice()].as_slice(),  &[&session_url, &texture_domain]);
+                        libraries_cmd.push(patched_auth);
+                        continue;
                     }
-                    libraries_cmd.push([libs.to_str().unwrap(), ";"].concat());
                 }
+                libraries_cmd.push(libs);
             }
-            libraries_cmd.push(client_jar.to_str().unwrap().to_string());
-            cmd.arg(libraries_cmd.concat());
-            cmd.arg(config.main_class.clone());
-
-            let mut game_dir = self.config.instances_path();
-            game_dir.push(&instance_name);
-            game_dir.push("data");
-
-            let mut assets_dir = self.config.assets_path();
-
-            let minecraft_arguments = minecraft_arguments.unwrap();
-            let minecraft_arguments = minecraft_arguments.split(" ");
-            for minecraft_argument in minecraft_arguments {
-                cmd.arg(match minecraft_argument {
-                    "${auth_player_name}" => username,
-                    "${version_name}" => &instance_name,
-                    "${game_directory}" => game_dir.to_str().unwrap(),
-                    "${assets_root}" => assets_dir.to_str().unwrap(),
-                    "${assets_index_name}" => &config.asset_index.as_ref().unwrap().id,
-                    "${auth_uuid}" => &uuid,
-                    "${auth_access_token}" => &token,
-                    "${user_properties}" => "{}",
-                    "${user_type}" => "mojang",
-                    "${version_type}" => "modified",
-                    _ => minecraft_argument
-                });
+        }
+        libraries_cmd.push(client_jar.clone());
+        let classpath = match std::env::join_paths(libraries_cmd) {
+            Ok(classpath) => classpath,
+            Err(e) => {
+                let _ = sender.send(format!("Error: {}", e));
+                return;
             }
+        };
+
+        let mut game_dir = self.config.instances_path();
+        game_dir.push(&instance_name);
+        game_dir.push("data");
+
+        let mut assets_dir = self.config.assets_path();
+        let game_assets = self.prepare_legacy_assets(&config, &game_dir);
+
+        let resolution = settings.window_width.or(self.config.window_width).zip(settings.window_height.or(self.config.window_height));
+        let mut features = Vec::new();
+        if resolution.is_some() {
+            features.push("has_custom_resolution");
+        }
 
-            //cmd.args(["--username", username, "--version", &instance_name, "--gameDir", game_dir.to_str().unwrap(), "--assetsDir", assets_dir.to_str().unwrap(), "--assetIndex", &config.assetIndex.id, "--uuid", &uuid, "--accessToken", &token, "--userProperties", "{}", "--userType", "mojang", "--width", "925", "--height", "530"]);
-            assets_dir.push("skins");
-            let _ = std::fs::remove_dir_all(assets_dir);
-            if let Some(server) = special_server {
-                cmd.arg("--server");
-                cmd.arg(&server.domain);
-                cmd.arg("--port");
-                cmd.arg(server.port.to_string());
+        let (width, height) = resolution.unwrap_or_default();
+        let variables: HashMap<&str, String> = HashMap::from([
+            ("auth_player_name", username.to_string()),
+            ("auth_session", token.clone()),
+            ("version_name", instance_name.clone()),
+            ("game_directory", game_dir.to_str().unwrap().to_string()),
+            ("assets_root", assets_dir.to_str().unwrap().to_string()),
+            ("game_assets", game_assets.to_str().unwrap().to_string()),
+            ("assets_index_name", config.asset_index.as_ref().unwrap().id.clone()),
+            ("auth_uuid", uuid.clone()),
+            ("auth_access_token", token.clone()),
+            ("auth_xuid", String::new()),
+            ("clientid", String::new()),
+            ("user_properties", "{}".to_string()),
+            ("user_type", user_type.to_string()),
+            ("version_type", "modified".to_string()),
+            ("resolution_width", width.to_string()),
+            ("resolution_height", height.to_string()),
+            ("natives_directory", natives_path.to_str().unwrap().to_string()),
+            ("library_directory", self.config.libraries_path().to_str().unwrap().to_string()),
+            ("classpath_separator", CLASSPATH_SEPARATOR.to_string()),
+            ("classpath", classpath.to_string_lossy().to_string()),
+            ("launcher_name", "XCraft".to_string()),
+            ("launcher_version", env!("CARGO_PKG_VERSION").to_string()),
+        ]);
+
+        match config.jvm_arguments(&features) {
+            Some(jvm_arguments) => {
+                for jvm_argument in jvm_arguments {
+                    // Passed as is so that non-UTF-8 library paths survive
+                    if jvm_argument == "${classpath}" {
+                        cmd.arg(&classpath);
+                        continue;
+                    }
+                    cmd.arg(substitute_variables(&jvm_argument, &variables));
+                }
             }
+            None => {
+                cmd.arg(["-Djava.library.path=", natives_path.to_str().unwrap() ].concat());
+                cmd.arg("-cp");
+                cmd.arg(&classpath);
+            }
+        }
+        cmd.arg(config.main_class.clone());
+
+        for minecraft_argument in config.game_arguments(&features) {
+            cmd.arg(substitute_variables(&minecraft_argument, &variables));
+        }
+
+        // Legacy minecraftArguments have no resolution placeholders
+        if let Some((width, height)) = resolution.filter(|_| config.arguments.is_none()) {
+            cmd.args(["--width", &width.to_string(), "--height", &height.to_string()]);
+        }
+
+        //cmd.args(["--username", username, "--version", &instance_name, "--gameDir", game_dir.to_str().unwrap(), "--assetsDir", assets_dir.to_str().unwrap(), "--assetIndex", &config.assetIndex.id, "--uuid", &uuid, "--accessToken", &token, "--userProperties", "{}", "--userType", "mojang", "--width", "925", "--height", "530"]);
+        assets_dir.push("skins");
+        let _ = std::fs::remove_dir_all(assets_dir);
+        if let Some(server) = special_server {
+            cmd.arg("--server");
+            cmd.arg(&server.domain);
+            cmd.arg("--port");
+            cmd.arg(server.port.to_string());
+        }
+        if settings.fullscreen {
+            cmd.arg("--fullscreen");
+        }
+        cmd.args(&settings.game_args);
+        
+        let mut child = cmd.spawn().unwrap();
+
+        tokio::spawn(async move {
             
-            let mut child = cmd.spawn().unwrap();
-
-            tokio::spawn(async move {
-                
-                if let Some(stdout) = child.stdout.take() {
-                    if let Some(stderr) = child.stderr.take() {
-                        let out_reader = BufReader::new(stdout);
-                        let mut out_lines = out_reader.lines();
-
-                        let err_reader = BufReader::new(stderr);
-                        let mut err_lines = err_reader.lines();
-                
-                        loop {
-                            tokio::select! {
-                                Ok(Some(line)) = out_lines.next_line() => {
-                                    let _ = sender.send(line);
-                                }
-                                Ok(Some(line)) = err_lines.next_line() => {
-                                    let _ = sender.send(line);
-                                }
-                                else => break,
+            if let Some(stdout) = child.stdout.take() {
+                if let Some(stderr) = child.stderr.take() {
+                    let out_reader = BufReader::new(stdout);
+                    let mut out_lines = out_reader.lines();
+
+                    let err_reader = BufReader::new(stderr);
+                    let mut err_lines = err_reader.lines();
+            
+                    loop {
+                        tokio::select! {
+                            Ok(Some(line)) = out_lines.next_line() => {
+                                let _ = sender.send(line);
+                            }
+                            Ok(Some(line)) = err_lines.next_line() => {
+                                let _ = sender.send(line);
                             }
+                            else => break,
                         }
-                        // end of minecraft launch
                     }
+                    // end of minecraft launch
                 }
-            });
-        }
+            }
+        });
     }
 
 
-    pub async fn import_multimc(&self, instance_path: PathBuf, sender: UnboundedSender<(u8, String)>) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let (sx, mut rx) = mpsc::unbounded_channel();
-
+    /// Imports a modpack archive, telling MultiMC/Prism, Modrinth and CurseForge packs apart by their manifest.
+    pub async fn import_pack(&self, pack_path: PathBuf, sender: UnboundedSender<(u8, String)>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let archive = zip::ZipArchive::new(std::fs::File::open(&pack_path)?)?;
+        let names = archive.file_names().map(|n| n.to_string()).collect::<Vec<String>>();
+        if names.iter().any(|n| n == "modrinth.index.json") {
+            self.import_mrpack(pack_path, sender).await
+        } else if names.iter().any(|n| n == "manifest.json") {
+            self.import_curseforge(pack_path, sender).await
+        } else if names.iter().any(|n| n.ends_with("mmc-pack.json")) {
+            self.import_multimc(pack_path, sender).await
+        } else {
+            Err("Unknown modpack format".into())
+        }
+    }
 
-        let instance_name = instance_path.file_name().unwrap().to_str().unwrap();
-        let instance_name = [&instance_name[..instance_name.len()-4], "_", &util::random_string(4)].concat();
+    /// Extracts a pack archive into a fresh `<file name>_<random>` instance directory.
+    fn unpack_into_instance(&self, pack_path: &Path, strip_toplevel: bool) -> Result<(String, PathBuf), Box<dyn Error + Send + Sync>> {
+        let file_stem = pack_path.file_stem().unwrap_or_default().to_string_lossy();
+        let instance_name = [&file_stem, "_", util::random_string(4).as_str()].concat();
         let mut instance_dir = self.config.instances_path();
         instance_dir.push(&instance_name);
-        let _ = std::fs::create_dir_all(&instance_dir);
-        let data = std::fs::read(instance_path)?;
-        zip_extract::extract(Cursor::new(data), &instance_dir, true)?;
-        let mut multimc_data = instance_dir.clone();
-        multimc_data.push(".minecraft");
-        let mut data_dir = instance_dir.clone();
+        std::fs::create_dir_all(&instance_dir)?;
+        let data = std::fs::read(pack_path)?;
+        zip_extract::extract(Cursor::new(data), &instance_dir, strip_toplevel)?;
+        Ok((instance_name, instance_dir))
+    }
+
+    pub async fn import_multimc(&self, instance_path: PathBuf, sender: UnboundedSender<(u8, String)>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (instance_name, instance_dir) = self.unpack_into_instance(&instance_path, true)?;
+        let result = self.install_multimc(&instance_name, &instance_dir, sender).await;
+        if result.is_err() {
+            let _ = std::fs::remove_dir_all(&instance_dir);
+        }
+        result
+    }
+
+    async fn install_multimc(&self, instance_name: &str, instance_dir: &Path, sender: UnboundedSender<(u8, String)>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // MultiMC keeps the game in `.minecraft`, Prism Launcher in `minecraft`
+        let mut data_dir = instance_dir.to_path_buf();
         data_dir.push("data");
-        std::fs::rename(multimc_data, data_dir)?;
+        if let Some(game_dir) = [".minecraft", "minecraft"].iter().map(|d| instance_dir.join(d)).find(|d| d.exists()) {
+            std::fs::rename(game_dir, data_dir)?;
+        }
 
-        let mut pack_path = instance_dir.clone();
+        let mut pack_path = instance_dir.to_path_buf();
         pack_path.push("mmc-pack.json");
         println!("pack_path {}", pack_path.to_str().unwrap());
         let multimc_data = std::fs::read(pack_path)?;
         let pack_mmc: Pack = serde_json::from_slice(&multimc_data)?;
 
-        let mut minecraft_config = None;
-        let mut forge_version = None;
-
-        for component in pack_mmc.components.iter().filter(|c| c.cached_name.is_some()) {
-            match component.cached_name.as_ref().unwrap().as_str() {
-                "Minecraft" => minecraft_config = Some(crate::minecraft::versions::find_version_object(&component.version).await?),
-                "Forge" => {
-                    forge_version = Some(component.version.clone());
+        let mut patches_dir = instance_dir.to_path_buf();
+        patches_dir.push("patches");
+
+        let mut minecraft_version = None;
+        let mut loader = None;
+
+        for component in &pack_mmc.components {
+            match component.uid.as_str() {
+                "net.minecraft" => minecraft_version = Some(component.version.clone()),
+                "net.minecraftforge" => loader = Some(("forge", component.version.clone())),
+                "net.neoforged" => loader = Some(("neoforge", component.version.clone())),
+                "net.fabricmc.fabric-loader" => loader = Some(("fabric", component.version.clone())),
+                "org.quiltmc.quilt-loader" => loader = Some(("quilt", component.version.clone())),
+                // Pulled in by the version and loader JSONs themselves, moved to the pinned version once resolved
+                uid if minecraft::multimc::pinned_library(uid).is_some() => {}
+                // Custom components come entirely from their patch file
+                uid if patches_dir.join([uid, ".json"].concat()).exists() => {}
+                uid => {
+                    return Err(format!("Unsupported MultiMC component {} ({})", component.cached_name.as_deref().unwrap_or(uid), uid).into());
                 }
-                _ => {}
             }
         }
 
-        let mut overall_size = 0;
-        let mut cnt = 0;
+        let Some(minecraft_version) = minecraft_version else {
+            return Err("Pack has no Minecraft component".into());
+        };
+
+        let mut queue = util::DownloadQueue::new(self.config.download_threads);
+        let (mut config, installer) = self.prepare_instance(instance_name, &minecraft_version, loader, &mut queue, &sender).await?;
+
+        let mut settings = InstanceSettings::default();
+        let mut cfg_path = instance_dir.to_path_buf();
+        cfg_path.push("instance.cfg");
+        if let Ok(data) = std::fs::read_to_string(&cfg_path) {
+            let cfg = minecraft::multimc::parse_instance_cfg(&data);
+            let enabled = |key: &str| cfg.get(key).is_some_and(|v| v == "true");
+            if enabled("OverrideMemory") {
+                settings.min_memory = cfg.get("MinMemAlloc").and_then(|v| v.parse().ok());
+                settings.max_memory = cfg.get("MaxMemAlloc").and_then(|v| v.parse().ok());
+            }
+            // A java path from another machine is worse than the default one
+            if enabled("OverrideJavaLocation") {
+                settings.java_path = cfg.get("JavaPath").filter(|p| !p.is_empty() && Path::new(p).exists()).cloned();
+            }
+            if enabled("OverrideJavaArgs") {
+                settings.jvm_args = cfg.get("JvmArgs").map(|a| a.split_whitespace().map(|a| a.to_string()).collect()).unwrap_or_default();
+            }
+            if enabled("OverrideWindow") {
+                settings.window_width = cfg.get("MinecraftWinWidth").and_then(|v| v.parse().ok());
+                settings.window_height = cfg.get("MinecraftWinHeight").and_then(|v| v.parse().ok());
+            }
+        }
 
-        let mut client_json_path = self.config.instances_path();
-        client_json_path.push(&instance_name);
-        client_json_path.push("client.json");
+        for component in &pack_mmc.components {
+            let Ok(data) = std::fs::read(patches_dir.join([&component.uid, ".json"].concat())) else { continue; };
+            let patch: minecraft::multimc::Patch = serde_json::from_slice(&data)?;
+            patch.apply(&mut config);
+            settings.jvm_args.extend(patch.jvm_args);
+        }
+        for component in &pack_mmc.components {
+            minecraft::multimc::apply_pinned(component, &mut config);
+        }
+        self.save_instance_settings(instance_name, &settings)?;
 
-        if minecraft_config.is_some() {
-            let config = minecraft_config.clone().unwrap();
-            let config_cl = config.clone();
-            
-            overall_size = config.downloads.as_ref().unwrap().client.size as usize;
-            cnt = 0;
+        self.finish_instance(instance_name, &config, installer, queue, sender).await
+    }
 
-            let client_jar_url = config.downloads.as_ref().unwrap().client.url.clone();
+    pub async fn import_mrpack(&self, pack_path: PathBuf, sender: UnboundedSender<(u8, String)>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (instance_name, instance_dir) = self.unpack_into_instance(&pack_path, false)?;
+        let result = self.install_mrpack(&instance_name, &instance_dir, sender).await;
+        if result.is_err() {
+            let _ = std::fs::remove_dir_all(&instance_dir);
+        }
+        result
+    }
 
-            let mut client_jar_path = self.config.instances_path();
-            client_jar_path.push(&instance_name);
-            client_jar_path.push("client.jar");
+    async fn install_mrpack(&self, instance_name: &str, instance_dir: &Path, sender: UnboundedSender<(u8, String)>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let index: minecraft::modrinth::Index = serde_json::from_slice(&std::fs::read(instance_dir.join("modrinth.index.json"))?)?;
+        let minecraft_version = index.dependencies.get("minecraft").ok_or("Pack has no minecraft dependency")?;
+        let loader = index.loader();
 
-            std::fs::write(&client_json_path, serde_json::to_string_pretty(&config_cl).unwrap())?;
+        let mut data_dir = instance_dir.to_path_buf();
+        data_dir.push("data");
+        for overrides in ["overrides", "client-overrides"] {
+            let overrides_dir = instance_dir.join(overrides);
+            if overrides_dir.exists() {
+                util::copy_dir_all(&overrides_dir, &data_dir)?;
+                std::fs::remove_dir_all(&overrides_dir)?;
+            }
+        }
 
-            let _ = util::download_file(&client_jar_url, client_jar_path.to_str().unwrap(), sx.clone(), "Downloading client.jar", false).await;
-            cnt += 1;
+        let mut queue = util::DownloadQueue::new(self.config.download_threads);
+        for file in index.files.iter().filter(|f| f.is_client_file()) {
+            let Some(url) = file.downloads.first() else { continue; };
+            let Some(relative) = util::safe_relative_path(&file.path) else {
+                return Err(format!("Pack file escapes the instance: {}", file.path).into());
+            };
+            queue.push_if_invalid(url, data_dir.join(relative), file.hashes.get("sha1").map(|h| h.as_str()), file.file_size, "Downloading mods");
+        }
 
-            let libraries = self.config.libraries_path();
+        let (config, installer) = self.prepare_instance(instance_name, minecraft_version, loader, &mut queue, &sender).await?;
+        self.finish_instance(instance_name, &config, installer, queue, sender).await
+    }
 
-            for i in 0..config.libraries.len() {
-                let library = &config.libraries[i];
-                if let Some(artifact) = &library.downloads.artifact {
-                    let mut dl_path = libraries.clone();
-                    let mut dl_pp = libraries.clone();
-                    dl_pp.push(library.to_pathbuf_path());
-                    let _ = std::fs::create_dir_all(dl_pp);
-                    dl_path.push(library.to_pathbuf_file(false));
-                    if File::open(dl_path.to_str().unwrap()).await.is_err() {
-                        overall_size += artifact.size as usize;
-                        let _ = util::download_file(&artifact.url, dl_path.to_str().unwrap(), sx.clone(), "Downloading libraries", false).await;
-                        cnt += 1;
-                    }
-                }
+    pub async fn import_curseforge(&self, pack_path: PathBuf, sender: UnboundedSender<(u8, String)>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (instance_name, instance_dir) = self.unpack_into_instance(&pack_path, false)?;
+        let result = self.install_curseforge(&instance_name, &instance_dir, sender).await;
+        if result.is_err() {
+            let _ = std::fs::remove_dir_all(&instance_dir);
+        }
+        result
+    }
 
-                if let Some(classifiers) = &library.downloads.classifiers {
-                    if let Some(natives) = &classifiers.natives {
-                        let mut dl_path = libraries.clone();
-                        dl_path.push(&natives.path);
-                        let t_p = dl_path.to_str().unwrap().split("/").collect::<Vec<&str>>();
-                        let t_p = t_p[..t_p.len()-1].join("/");
-                        let _ = std::fs::create_dir_all(&t_p);
-                        if File::open(dl_path.to_str().unwrap()).await.is_err() {
-                            overall_size += natives.size as usize;
-                            let _ = util::download_file(&natives.url, dl_path.to_str().unwrap(), sx.clone(), "Downloading natives", false).await;
-                            cnt += 1;
-                        }
-                    }
+    async fn install_curseforge(&self, instance_name: &str, instance_dir: &Path, sender: UnboundedSender<(u8, String)>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let manifest: minecraft::curseforge::Manifest = serde_json::from_slice(&std::fs::read(instance_dir.join("manifest.json"))?)?;
+        let loader = manifest.loader();
+
+        let mut data_dir = instance_dir.to_path_buf();
+        data_dir.push("data");
+        let overrides_dir = instance_dir.join(&manifest.overrides);
+        if overrides_dir.exists() {
+            util::copy_dir_all(&overrides_dir, &data_dir)?;
+            std::fs::remove_dir_all(&overrides_dir)?;
+        }
+
+        let api = minecraft::curseforge::Api::new(&self.config.curseforge_api_url, self.config.curseforge_api_key.as_deref());
+        let file_ids = manifest.files.iter().map(|f| f.file_id).collect::<Vec<u64>>();
+        let files = api.fetch_files(&file_ids).await?;
+        let mod_ids = files.iter().map(|f| f.mod_id).collect::<Vec<u64>>();
+        // Without the project classes everything goes to mods/, which is right for most files anyway
+        let classes = api.fetch_mod_classes(&mod_ids).await.unwrap_or_default();
+
+        let mut queue = util::DownloadQueue::new(self.config.download_threads);
+        for file in &files {
+            let Some(file_name) = util::safe_relative_path(&file.file_name) else { continue; };
+            let folder = minecraft::curseforge::class_folder(classes.get(&file.mod_id).copied());
+            queue.push_if_invalid(&file.download_url(), data_dir.join(folder).join(file_name), file.sha1(), Some(file.file_length), "Downloading mods");
+        }
+
+        let (config, installer) = self.prepare_instance(instance_name, &manifest.minecraft.version, loader, &mut queue, &sender).await?;
+        self.finish_instance(instance_name, &config, installer, queue, sender).await
+    }
+
+    /// Writes an instance as a MultiMC/Prism zip (`multimc`) or as a Modrinth `.mrpack` (`mrpack`).
+    /// Mods go in as plain files, so the `.mrpack` has an empty file list and carries everything in `overrides/`.
+    pub fn export_instance(&self, instance_name: &str, target: &Path, format: &str, filter: &ExportFilter) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let instance_dir = self.instance_dir(instance_name)?;
+        let config: VersionConfig = serde_json::from_slice(&std::fs::read(instance_dir.join("client.json"))?)?;
+        let loader = config.loader();
+
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(target)?);
+        let options = zip::write::SimpleFileOptions::default();
+
+        let data_prefix = match format {
+            "mrpack" => {
+                let mut dependencies = HashMap::from([("minecraft".to_string(), config.id.clone())]);
+                let dependency = loader.as_ref().and_then(|(loader, version)| {
+                    minecraft::modrinth::LOADER_DEPENDENCIES.iter().find(|(_, l)| l == loader).map(|(dependency, _)| (dependency.to_string(), version.clone()))
+                });
+                dependencies.extend(dependency);
+                let index = minecraft::modrinth::Index { format_version: 1, game: "minecraft".to_string(), version_id: "1.0.0".to_string(), name: instance_name.to_string(), files: Vec::new(), dependencies };
+                zip.start_file("modrinth.index.json", options)?;
+                zip.write_all(serde_json::to_string_pretty(&index)?.as_bytes())?;
+                "overrides/".to_string()
+            }
+            _ => {
+                let mut components = vec![minecraft::multimc::Component { cached_name: Some("Minecraft".to_string()), version: config.id.clone(), uid: "net.minecraft".to_string() }];
+                let loader_component = loader.as_ref().and_then(|(loader, version)| {
+                    minecraft::multimc::loader_component(loader).map(|(uid, name)| minecraft::multimc::Component { cached_name: Some(name.to_string()), version: version.clone(), uid: uid.to_string() })
+                });
+                components.extend(loader_component);
+                let pack = Pack { format_version: 1, components };
+                zip.start_file([instance_name, "/mmc-pack.json"].concat(), options)?;
+                zip.write_all(serde_json::to_string_pretty(&pack)?.as_bytes())?;
+
+                let settings = self.load_instance_settings(instance_name);
+                let mut cfg = vec!["InstanceType=OneSix".to_string(), ["name=", instance_name].concat()];
+                if settings.min_memory.is_some() || settings.max_memory.is_some() {
+                    cfg.push("OverrideMemory=true".to_string());
+                    cfg.extend(settings.min_memory.map(|m| format!("MinMemAlloc={}", m)));
+                    cfg.extend(settings.max_memory.map(|m| format!("MaxMemAlloc={}", m)));
+                }
+                if let Some(java_path) = &settings.java_path {
+                    cfg.extend(["OverrideJavaLocation=true".to_string(), ["JavaPath=", java_path].concat()]);
+                }
+                if !settings.jvm_args.is_empty() {
+                    cfg.extend(["OverrideJavaArgs=true".to_string(), ["JvmArgs=", &settings.jvm_args.join(" ")].concat()]);
+                }
+                if let (Some(width), Some(height)) = (settings.window_width, settings.window_height) {
+                    cfg.extend(["OverrideWindow=true".to_string(), format!("MinecraftWinWidth={}", width), format!("MinecraftWinHeight={}", height)]);
+                }
+                zip.start_file([instance_name, "/instance.cfg"].concat(), options)?;
+                zip.write_all(cfg.join("\n").as_bytes())?;
+                [instance_name, "/.minecraft/"].concat()
+            }
+        };
+
+        let data_dir = instance_dir.join("data");
+        if data_dir.exists() {
+            for file in util::list_files(&data_dir)? {
+                let relative = file.strip_prefix(&data_dir)?.components()
+                    .map(|c| c.as_os_str().to_string_lossy().to_string())
+                    .collect::<Vec<String>>()
+                    .join("/");
+                if !filter.allows(&relative) {
+                    continue;
                 }
+                zip.start_file([data_prefix.as_str(), &relative].concat(), options)?;
+                std::io::copy(&mut std::fs::File::open(&file)?, &mut zip)?;
             }
+        }
+        zip.finish()?;
+        Ok(())
+    }
 
-            let assets_path = self.config.assets_path();
+    /// Writes `client.json` for vanilla `minecraft_version` with an optional loader on top and queues the client and assets.
+    async fn prepare_instance(&self, instance_name: &str, minecraft_version: &str, loader: Option<(&str, String)>, queue: &mut util::DownloadQueue, sender: &UnboundedSender<(u8, String)>) -> Result<(VersionConfig, Option<minecraft::forge::Installer>), Box<dyn Error + Send + Sync>> {
+        let mut config = minecraft::versions::find_version_object(minecraft_version).await?;
 
-            let mut indexes = assets_path.clone();
-            indexes.push("indexes");
-            let _ = std::fs::create_dir_all(indexes);
+        let mut client_jar_path = self.config.instances_path();
+        client_jar_path.push(instance_name);
+        client_jar_path.push("client.jar");
+        let client = &config.downloads.as_ref().unwrap().client;
+        queue.push_if_invalid(&client.url, client_jar_path, Some(&client.sha1), Some(client.size), "Downloading client.jar");
 
-            let mut objects = assets_path.clone();
-            objects.push("objects");
-            let _ = std::fs::create_dir_all(objects);
+        self.queue_assets(&config, queue).await?;
 
-            let mut index = assets_path.clone();
-            index.push(config.asset_index.as_ref().unwrap().to_path());
+        let mut installer = None;
+        if let Some((loader, loader_version)) = loader {
+            let (patch, loader_installer) = self.fetch_loader_patch(loader, minecraft_version, &loader_version, sender).await?;
+            config.merge(patch);
+            installer = loader_installer;
+        }
+        Ok((config, installer))
+    }
 
-            let _ = util::download_file(&config.asset_index.as_ref().unwrap().url, index.to_str().unwrap(), sx.clone(), "Downloading assets indexes", false).await;
-            cnt += 1;
+    /// Saves the final `client.json`, queues libraries and java, and starts the downloads and loader processors.
+    async fn finish_instance(&self, instance_name: &str, config: &VersionConfig, installer: Option<minecraft::forge::Installer>, mut queue: util::DownloadQueue, sender: UnboundedSender<(u8, String)>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut instance_dir = self.config.instances_path();
+        instance_dir.push(instance_name);
+        std::fs::write(instance_dir.join("client.json"), serde_json::to_string_pretty(config).unwrap())?;
 
-            let asset_index = config.asset_index.as_ref().unwrap().url.clone();
+        self.queue_libraries(&config.libraries, &mut queue);
+        if let Err(e) = self.queue_runtime(config, &mut queue).await {
+            println!("Error: {}", e);
+        }
+        let post_install = installer.map(|installer| {
+            self.queue_libraries(&installer.libraries, &mut queue);
+            self.post_install(installer, config, instance_dir.join("client.jar"))
+        });
 
-            overall_size += config.asset_index.as_ref().unwrap().size as usize;
-            overall_size += config.asset_index.as_ref().unwrap().total_size as usize;
+        spawn_downloads(queue, sender, post_install);
+        Ok(())
+    }
 
-            let assets = crate::minecraft::assets::fetch_assets_list(&asset_index).await.unwrap().objects;
+    pub async fn new_vanilla_instance(&mut self, config: VersionConfig, version_object: &Version, sender: UnboundedSender<(u8, String)>) {
+        let mut queue = util::DownloadQueue::new(self.config.download_threads);
 
-            for (_key, asset) in assets {
-                let mut single_object = assets_path.clone();
-                single_object.push(asset.to_path());
+        let mut instances = self.config.instances_path();
+        instances.push(&config.id);
 
-                let mut single_object_path = assets_path.clone();
-                single_object_path.push(asset.to_small_path());
-                let _ = std::fs::create_dir_all(single_object_path);
+        let _ = std::fs::create_dir_all(&instances);
 
-                if File::open(single_object.to_str().unwrap()).await.is_err() {
-                    let _ = util::download_file(&asset.to_url(), single_object.to_str().unwrap(), sx.clone(), "Downloading assets objects", false).await;
-                    cnt += 1;
-                }
-            }
-        }
+        instances.push("client.jar");
 
-        if minecraft_config.is_some() && forge_version.is_some() {
-            let mut forge_installer_path = self.config.libraries_path();
-            forge_installer_path.push("forge_installer.jar");
+        let client = &config.downloads.as_ref().unwrap().client;
 
-            let mut forge_installer_unpack = self.config.libraries_path();
-            forge_installer_unpack.push("installer_unpacked");
+        let mut client_json_path = self.config.instances_path();
+        client_json_path.push(&config.id);
+        client_json_path.push("client.json");
 
-            std::fs::create_dir_all(&forge_installer_unpack)?;
+        queue.push(&version_object.url, client_json_path, Some(&version_object.sha1), None, "Downloading client.json");
+        queue.push_if_invalid(&client.url, instances, Some(&client.sha1), Some(client.size), "Downloading client.jar");
 
-            let forge_installer_url = format!("https://maven.minecraftforge.net/net/minecraftforge/forge/{}-{}/forge-{}-{}-installer.jar", minecraft_config.as_ref().unwrap().id, forge_version.as_ref().unwrap(), minecraft_config.as_ref().unwrap().id, forge_version.as_ref().unwrap());
+        self.queue_libraries(&config.libraries, &mut queue);
+        if let Err(e) = self.queue_assets(&config, &mut queue).await {
+            let _ = sender.send((100, ["_", &e.to_string()].concat()));
+            return;
+        }
+        if let Err(e) = self.queue_runtime(&config, &mut queue).await {
+            println!("Error: {}", e);
+        }
 
-            let _ = util::download_file(&forge_installer_url, forge_installer_path.to_str().unwrap(), sx.clone(), "Downloading forge installer", true).await;
-            cnt += 1;
+        spawn_downloads(queue, sender, None);
+    }
 
-            let forge_installer_data = std::fs::read(&forge_installer_path)?;
+    /// Version JSON of a mod loader (`forge`, `neoforge`, `fabric` or `quilt`) to merge on top of vanilla `game_version`.
+    /// Forge-style loaders also return the installer processors that have to run after the downloads.
+    async fn fetch_loader_patch(&self, loader: &str, game_version: &str, loader_version: &str, sender: &UnboundedSender<(u8, String)>) -> Result<(VersionConfig, Option<minecraft::forge::Installer>), Box<dyn Error + Send + Sync>> {
+        match loader {
+            "fabric" => Ok((minecraft::loaders::fetch_loader_profile(minecraft::loaders::FABRIC_META_URL, game_version, loader_version).await?, None)),
+            "quilt" => Ok((minecraft::loaders::fetch_loader_profile(minecraft::loaders::QUILT_META_URL, game_version, loader_version).await?, None)),
+            "forge" => self.unpack_installer(&minecraft::loaders::forge_installer_url(game_version, loader_version), sender).await,
+            "neoforge" => self.unpack_installer(&minecraft::loaders::neoforge_installer_url(game_version, loader_version), sender).await,
+            loader => Err(format!("Unknown loader {}", loader).into())
+        }
+    }
 
-            zip_extract::extract(Cursor::new(forge_installer_data), &forge_installer_unpack, true)?;
+    /// Downloads a Forge-style installer, copies the libraries it bundles under `maven/` and returns its `version.json`
+    /// along with the processors from `install_profile.json`.
+    async fn unpack_installer(&self, installer_url: &str, sender: &UnboundedSender<(u8, String)>) -> Result<(VersionConfig, Option<minecraft::forge::Installer>), Box<dyn Error + Send + Sync>> {
+        let mut installer_path = self.config.libraries_path();
+        installer_path.push("forge_installer.jar");
 
-            let mut forge_library_path = self.config.libraries_path();
-            forge_library_path.push("net");
-            forge_library_path.push("minecraftforge");
-            forge_library_path.push("forge");
-            forge_library_path.push(format!("{}-{}", minecraft_config.as_ref().unwrap().id, forge_version.as_ref().unwrap()));
+        let mut installer_unpack = self.config.libraries_path();
+        installer_unpack.push("installer_unpacked");
 
-            let mut forge_version_json = forge_installer_unpack.clone();
-            forge_version_json.push("version.json");
+        std::fs::create_dir_all(&installer_unpack)?;
 
-            let mut forge_installer_library = forge_installer_unpack.clone();
-            forge_installer_library.push("maven");
-            forge_installer_library.push("net");
-            forge_installer_library.push("minecraftforge");
-            forge_installer_library.push("forge");
-            forge_installer_library.push(format!("{}-{}", minecraft_config.as_ref().unwrap().id, forge_version.as_ref().unwrap()));
-            forge_installer_library.push(format!("forge-{}-{}.jar", minecraft_config.as_ref().unwrap().id, forge_version.as_ref().unwrap()));
+        let mut installer_queue = util::DownloadQueue::new(1);
+        installer_queue.push(installer_url, installer_path.clone(), None, None, "Downloading loader installer");
+        let report = installer_queue.run(sender).await;
+        if let Some(failure) = report.failed.first() {
+            return Err(format!("Failed to download loader installer: {}", failure.error).into());
+        }
 
-            std::fs::create_dir_all(&forge_library_path)?;
+        let installer_data = std::fs::read(&installer_path)?;
+        zip_extract::extract(Cursor::new(installer_data), &installer_unpack, true)?;
 
-            forge_library_path.push(format!("forge-{}-{}.jar", minecraft_config.as_ref().unwrap().id, forge_version.as_ref().unwrap()));
+        let mut bundled_maven = installer_unpack.clone();
+        bundled_maven.push("maven");
+        if bundled_maven.exists() {
+            util::copy_dir_all(&bundled_maven, &self.config.libraries_path())?;
+        }
 
-            std::fs::copy(&forge_installer_library, &forge_library_path)?;
+        let mut version_json = installer_unpack.clone();
+        version_json.push("version.json");
+        let version_json: VersionConfig = serde_json::from_slice(&std::fs::read(&version_json)?)?;
 
-            let version_json = std::fs::read(&forge_version_json)?;
-            let version_json: VersionConfig = serde_json::from_slice(&version_json)?;
+        // The processors read their inputs from the unpacked installer, so it is cleaned up after they run
+        let installer = minecraft::forge::Installer::load(&installer_unpack, &installer_path, &self.config.libraries_path())?;
+        if installer.is_none() {
+            std::fs::remove_dir_all(installer_unpack)?;
+            std::fs::remove_file(installer_path)?;
+        }
 
-            let mut edited = minecraft_config.clone().unwrap();
-            edited.main_class = version_json.main_class.clone();
-            edited.minecraft_arguments = version_json.minecraft_arguments;
-            edited.libraries.retain(|l| !version_json.libraries.iter().any(|t| t.name == l.name));
-            for i in 0..version_json.libraries.len() {
-                edited.libraries.push(version_json.libraries[i].clone());
-            }
+        Ok((version_json, installer))
+    }
 
-            std::fs::write(&client_json_path, serde_json::to_string_pretty(&edited).unwrap())?;
+    fn post_install(&self, installer: minecraft::forge::Installer, config: &VersionConfig, minecraft_jar: PathBuf) -> PostInstall {
+        PostInstall { installer, minecraft_jar, runtime_java: self.runtime_java(config), java: PathBuf::from(&self.config.java_path) }
+    }
 
-            std::fs::remove_dir_all(forge_installer_unpack)?;
-            std::fs::remove_file(forge_installer_path)?;
+    /// Installs vanilla `game_version` with a mod loader on top, naming the instance after the loader's version id.
+    pub async fn new_loader_instance(&mut self, loader: &str, game_version: &str, loader_version: &str, sender: UnboundedSender<(u8, String)>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut config = minecraft::versions::find_version_object(game_version).await?;
+        let (patch, installer) = self.fetch_loader_patch(loader, game_version, loader_version, &sender).await?;
+        let instance_name = patch.id.clone();
+        config.merge(patch);
 
-            let libraries = self.config.libraries_path();
+        let mut queue = util::DownloadQueue::new(self.config.download_threads);
 
-            for i in 0..edited.libraries.len() {
-                let library = &edited.libraries[i];
-                if let Some(artifact) = &library.downloads.artifact {
-                    let mut dl_path = libraries.clone();
-                    let mut dl_pp = libraries.clone();
-                    dl_pp.push(library.to_pathbuf_path());
-                    let _ = std::fs::create_dir_all(dl_pp);
-                    dl_path.push(library.to_pathbuf_file(false));
-                    if File::open(dl_path.to_str().unwrap()).await.is_err() {
-                        overall_size += artifact.size as usize;
-                        let _ = util::download_file(&artifact.url, dl_path.to_str().unwrap(), sx.clone(), "Downloading libraries", false).await;
-                        cnt += 1;
-                    }
-                }
+        let mut instance_dir = self.config.instances_path();
+        instance_dir.push(&instance_name);
+        std::fs::create_dir_all(&instance_dir)?;
 
-                if let Some(classifiers) = &library.downloads.classifiers {
-                    if let Some(natives) = &classifiers.natives {
-                        let mut dl_path = libraries.clone();
-                        dl_path.push(&natives.path);
-                        let t_p = dl_path.to_str().unwrap().split("/").collect::<Vec<&str>>();
-                        let t_p = t_p[..t_p.len()-1].join("/");
-                        let _ = std::fs::create_dir_all(&t_p);
-                        if File::open(dl_path.to_str().unwrap()).await.is_err() {
-                            overall_size += natives.size as usize;
-                            let _ = util::download_file(&natives.url, dl_path.to_str().unwrap(), sx.clone(), "Downloading natives", false).await;
-                            cnt += 1;
-                        }
-                    }
-                }
-            }
+        let mut client_json_path = instance_dir.clone();
+        client_json_path.push("client.json");
+        std::fs::write(&client_json_path, serde_json::to_string_pretty(&config).unwrap())?;
+
+        let mut client_jar_path = instance_dir.clone();
+        client_jar_path.push("client.jar");
+        let client = &config.downloads.as_ref().unwrap().client;
+        queue.push_if_invalid(&client.url, client_jar_path.clone(), Some(&client.sha1), Some(client.size), "Downloading client.jar");
+
+        self.queue_libraries(&config.libraries, &mut queue);
+        self.queue_assets(&config, &mut queue).await?;
+        if let Err(e) = self.queue_runtime(&config, &mut queue).await {
+            println!("Error: {}", e);
         }
 
-        tokio::spawn(async move {
-            let mut current_size = 0;
-            let mut current_cnt = 0;
-            while let Some((size, status)) = rx.recv().await {
-                current_size += size;
-                current_cnt += 1;
-                let _ = sender.send((((current_size as f32 / overall_size as f32) * 100.0) as u8, status));
-                if current_cnt >= cnt {
-                    let _ = sender.send((100, "_".to_string()));
-                }
-            }
+        let post_install = installer.map(|installer| {
+            self.queue_libraries(&installer.libraries, &mut queue);
+            self.post_install(installer, &config, client_jar_path)
         });
 
+        spawn_downloads(queue, sender, post_install);
         Ok(())
     }
 
-    pub async fn new_vanilla_instance(&mut self, config: VersionConfig, version_object: &Version, sender: UnboundedSender<(u8, String)>) {
-        
-        let (sx, mut rx) = mpsc::unbounded_channel();
-        
-        let mut instances = self.config.instances_path();
-        instances.push(&config.id);
-
-        let _ = std::fs::create_dir_all(&instances);
-
-        instances.push("client.jar");
-
-        let mut overall_size = config.downloads.as_ref().unwrap().client.size as usize;
-        let mut cnt = 0;
-
-        let client_jar_url = config.downloads.as_ref().unwrap().client.url.clone();
+    fn runtime_path(&self, component: &str) -> PathBuf {
+        let mut p = self.config.runtimes_path();
+        p.push(component);
+        p
+    }
 
-        let mut client_json_path = self.config.instances_path();
-        client_json_path.push(config.id);
-        client_json_path.push("client.json");
+    /// Managed runtime java for the version, whether it is downloaded yet or not.
+    fn runtime_java(&self, config: &VersionConfig) -> Option<PathBuf> {
+        let java_version = config.java_version.as_ref()?;
+        let mut java = self.runtime_path(&java_version.component);
+        java.push(minecraft::runtime::java_executable_path());
+        Some(java)
+    }
 
-        let _ = util::download_file(&version_object.url, client_json_path.to_str().unwrap(), sx.clone(), "Downloading client.json", false).await;
-        cnt += 1;
+    /// Java to launch the version with: its managed runtime when one is installed, the configured java otherwise.
+    fn java_executable(&self, config: &VersionConfig) -> PathBuf {
+        self.runtime_java(config).filter(|java| java.exists()).unwrap_or_else(|| PathBuf::from(&self.config.java_path))
+    }
 
-        let _ = util::download_file(&client_jar_url, instances.to_str().unwrap(), sx.clone(), "Downloading client.jar", false).await;
-        cnt += 1;
+    /// Queues the Mojang java runtime the version asks for through `javaVersion`.
+    async fn queue_runtime(&self, config: &VersionConfig, queue: &mut util::DownloadQueue) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let Some(java_version) = &config.java_version else { return Ok(()); };
+        let Some(platform) = minecraft::runtime::runtime_platform() else { return Ok(()); };
+
+        let manifest = minecraft::runtime::fetch_runtime_manifest().await?;
+        let entry = manifest.get(platform)
+            .and_then(|components| components.get(&java_version.component))
+            .and_then(|entries| entries.first())
+            .ok_or(format!("No {} runtime for {}", java_version.component, platform))?;
+        println!("Using java runtime {} {}", java_version.component, entry.version.name);
+
+        let files = minecraft::runtime::fetch_runtime_files(&entry.manifest.url).await?.files;
+        let runtime_dir = self.runtime_path(&java_version.component);
+
+        for (path, file) in files {
+            let mut file_path = runtime_dir.clone();
+            file_path.push(&path);
+            match file.r#type.as_str() {
+                "directory" => std::fs::create_dir_all(&file_path)?,
+                "file" => {
+                    let Some(downloads) = &file.downloads else { continue; };
+                    let raw = &downloads.raw;
+                    if file.executable {
+                        queue.push_executable_if_invalid(&raw.url, file_path, Some(&raw.sha1), Some(raw.size), "Downloading java runtime");
+                    } else {
+                        queue.push_if_invalid(&raw.url, file_path, Some(&raw.sha1), Some(raw.size), "Downloading java runtime");
+                    }
+                }
+                #[cfg(unix)]
+                "link" => {
+                    if let Some(target) = &file.target {
+                        if let Some(parent) = file_path.parent() {
+                            std::fs::create_dir_all(parent)?;
+                        }
+                        if std::fs::symlink_metadata(&file_path).is_err() {
+                            std::os::unix::fs::symlink(target, &file_path)?;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
 
+    fn queue_libraries(&self, version_libraries: &[VersionLibrary], queue: &mut util::DownloadQueue) {
         let libraries = self.config.libraries_path();
 
-        for i in 0..config.libraries.len() {
-            let library = &config.libraries[i];
+        for library in version_libraries.iter().filter(|l| l.is_allowed()) {
             if let Some(artifact) = &library.downloads.artifact {
+                // Forge leaves the url empty for jars its processors generate
+                if artifact.url.is_empty() {
+                    continue;
+                }
                 let mut dl_path = libraries.clone();
-                let mut dl_pp = libraries.clone();
-                dl_pp.push(library.to_pathbuf_path());
-                let _ = std::fs::create_dir_all(dl_pp);
                 dl_path.push(library.to_pathbuf_file(false));
-                if File::open(dl_path.to_str().unwrap()).await.is_err() {
-                    overall_size += artifact.size as usize;
-                    let _ = util::download_file(&artifact.url, dl_path.to_str().unwrap(), sx.clone(), "Downloading libraries", false).await;
-                    cnt += 1;
-                }
+                queue.push_if_invalid(&artifact.url, dl_path, Some(artifact.sha1.as_str()).filter(|s| !s.is_empty()), Some(artifact.size).filter(|s| *s > 0), "Downloading libraries");
+            } else if let Some(url) = library.artifact_url() {
+                let mut dl_path = libraries.clone();
+                dl_path.push(library.to_pathbuf_file(false));
+                queue.push_if_invalid(&url, dl_path, library.sha1.as_deref(), library.size, "Downloading libraries");
             }
 
-            if let Some(classifiers) = &library.downloads.classifiers {
-                if let Some(natives) = &classifiers.natives {
-                    let mut dl_path = libraries.clone();
-                    dl_path.push(&natives.path);
-                    let t_p = dl_path.to_str().unwrap().split("/").collect::<Vec<&str>>();
-                    let t_p = t_p[..t_p.len()-1].join("/");
-                    let _ = std::fs::create_dir_all(&t_p);
-                    if File::open(dl_path.to_str().unwrap()).await.is_err() {
-                        overall_size += natives.size as usize;
-                        let _ = util::download_file(&natives.url, dl_path.to_str().unwrap(), sx.clone(), "Downloading natives", false).await;
-                        cnt += 1;
-                    }
-                }
+            if let Some(natives) = library.native_download() {
+                let mut dl_path = libraries.clone();
+                dl_path.push(&natives.path);
+                queue.push_if_invalid(&natives.url, dl_path, Some(natives.sha1.as_str()).filter(|s| !s.is_empty()), Some(natives.size).filter(|s| *s > 0), "Downloading natives");
             }
         }
+    }
 
+    async fn queue_assets(&self, config: &VersionConfig, queue: &mut util::DownloadQueue) -> Result<(), Box<dyn Error + Send + Sync>> {
         let assets_path = self.config.assets_path();
-
-        let mut indexes = assets_path.clone();
-        indexes.push("indexes");
-        let _ = std::fs::create_dir_all(indexes);
-
-        let mut objects = assets_path.clone();
-        objects.push("objects");
-        let _ = std::fs::create_dir_all(objects);
+        let asset_index = config.asset_index.as_ref().unwrap();
 
         let mut index = assets_path.clone();
-        index.push(config.asset_index.as_ref().unwrap().to_path());
+        index.push(asset_index.to_path());
+        queue.push_if_invalid(&asset_index.url, index, Some(&asset_index.sha1), Some(asset_index.size), "Downloading assets indexes");
 
-        let _ = util::download_file(&config.asset_index.as_ref().unwrap().url.clone(), index.to_str().unwrap(), sx.clone(), "Downloading assets indexes", false).await;
-        cnt += 1;
-
-        let asset_index = config.asset_index.as_ref().unwrap().url.clone();
-
-        overall_size += config.asset_index.as_ref().unwrap().size as usize;
-        overall_size += config.asset_index.as_ref().unwrap().total_size as usize;
-
-        let assets = crate::minecraft::assets::fetch_assets_list(&asset_index).await.unwrap().objects;
+        let assets = crate::minecraft::assets::fetch_assets_list(&asset_index.url).await?.objects;
 
         for (_key, asset) in assets {
             let mut single_object = assets_path.clone();
             single_object.push(asset.to_path());
+            queue.push_if_invalid(&asset.to_url(), single_object, Some(&asset.hash), asset.size, "Downloading assets objects");
+        }
+        Ok(())
+    }
 
-            let mut single_object_path = assets_path.clone();
-            single_object_path.push(asset.to_small_path());
-            let _ = std::fs::create_dir_all(single_object_path);
+    /// Lays out virtual and resource-mapped asset indexes the way old clients expect them,
+    /// returning the directory `${game_assets}` should point to.
+    fn prepare_legacy_assets(&self, config: &VersionConfig, game_dir: &Path) -> PathBuf {
+        let assets_path = self.config.assets_path();
+        let Some(asset_index) = config.asset_index.as_ref() else { return assets_path; };
 
-            if File::open(single_object.to_str().unwrap()).await.is_err() {
-                let _ = util::download_file(&asset.to_url(), single_object.to_str().unwrap(), sx.clone(), "Downloading assets objects", false).await;
-                cnt += 1;
+        let mut index = assets_path.clone();
+        index.push(asset_index.to_path());
+        let Ok(data) = std::fs::read(&index) else { return assets_path; };
+        let Ok(assets) = serde_json::from_slice::<crate::minecraft::assets::Assets>(&data) else { return assets_path; };
+
+        let target = if assets.map_to_resources {
+            game_dir.join("resources")
+        } else if assets.is_virtual {
+            let mut p = assets_path.clone();
+            p.push("virtual");
+            p.push(&asset_index.id);
+            p
+        } else {
+            return assets_path;
+        };
+
+        for (name, asset) in &assets.objects {
+            let dest = target.join(name);
+            if util::is_file_valid(&dest, None, asset.size) {
+                continue;
             }
-        }
-
-        tokio::spawn(async move {
-            let mut current_size = 0;
-            let mut current_cnt = 0;
-            while let Some((size, status)) = rx.recv().await {
-                current_size += size;
-                current_cnt += 1;
-                let _ = sender.send((((current_size as f32 / overall_size as f32) * 100.0) as u8, status));
-                if current_cnt >= cnt {
-                    let _ = sender.send((100, "_".to_string()));
-                }
+            if let Some(parent) = dest.parent() {
+                let _ = std::fs::create_dir_all(parent);
             }
-        });
+            if let Err(e) = std::fs::copy(assets_path.join(asset.to_path()), &dest) {
+                println!("Failed to copy asset {}: {}", name, e);
+            }
+        }
+        target
     }
 
     pub fn init_dirs(&self) {
@@ -760,6 +1532,88 @@ impl Launcher {
     }
 }
 
+fn xcraft_server(session: Session, username: String, domain: String, session_server_port: u16, server_port: u16) -> LauncherServer {
+    LauncherServer {
+        domain,
+        port: server_port,
+        session_server_port,
+        auth: AuthBackend::XCraft,
+        credentials: LauncherCredentials::new(session.uuid, username, SessionTokens { access_token: session.access_token, refresh_token: session.refresh_token }, util::unix_time() + session.expires_in)
+    }
+}
+
+fn microsoft_credentials(account: minecraft::microsoft::Account) -> LauncherCredentials {
+    LauncherCredentials::new(account.uuid, account.username, SessionTokens { access_token: account.access_token, refresh_token: account.refresh_token }, util::unix_time() + account.expires_in)
+}
+
+/// Replaces every `${name}` placeholder in a version JSON argument, leaving unknown ones untouched.
+fn substitute_variables(argument: &str, variables: &HashMap<&str, String>) -> String {
+    let mut result = String::new();
+    let mut rest = argument;
+    while let Some(start) = rest.find("${") {
+        let Some(end) = rest[start..].find('}') else { break; };
+        let end = start + end;
+        result.push_str(&rest[..start]);
+        match variables.get(&rest[start + 2..end]) {
+            Some(value) => result.push_str(value),
+            None => result.push_str(&rest[start..=end])
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Shared files that no instance references anymore.
+pub struct OrphanReport {
+    pub files: Vec<PathBuf>,
+    pub bytes: u64
+}
+
+/// Which files of an instance's game directory go into an export, as `/`-separated path prefixes.
+/// An empty include list means everything.
+pub struct ExportFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>
+}
+
+impl ExportFilter {
+    fn allows(&self, relative: &str) -> bool {
+        let matches = |prefix: &String| {
+            let prefix = prefix.trim_matches('/');
+            relative == prefix || relative.starts_with(&[prefix, "/"].concat())
+        };
+        (self.include.is_empty() || self.include.iter().any(matches)) && !self.exclude.iter().any(matches)
+    }
+}
+
+/// Forge installer processors waiting for the downloads they depend on.
+struct PostInstall {
+    installer: minecraft::forge::Installer,
+    minecraft_jar: PathBuf,
+    runtime_java: Option<PathBuf>,
+    java: PathBuf
+}
+
+fn spawn_downloads(queue: util::DownloadQueue, sender: UnboundedSender<(u8, String)>, post_install: Option<PostInstall>) {
+    tokio::spawn(async move {
+        let report = queue.run(&sender).await;
+        if !report.failed.is_empty() {
+            let _ = sender.send((100, report.final_status()));
+            return;
+        }
+        if let Some(post_install) = post_install {
+            let java = post_install.runtime_java.filter(|java| java.exists()).unwrap_or(post_install.java);
+            if let Err(e) = post_install.installer.run(&java, &post_install.minecraft_jar, &sender).await {
+                println!("Error: {}", e);
+                let _ = sender.send((100, ["_Forge install failed: ", &e.to_string()].concat()));
+                return;
+            }
+        }
+        let _ = sender.send((100, report.final_status()));
+    });
+}
+
 #[derive(Serialize, Deserialize)]
 struct BackgroundFiles {
     name: String
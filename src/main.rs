@@ -2,10 +2,10 @@
 
 use std::sync::Mutex;
 
-use launcher::Launcher;
+use config::{AuthBackend, InstanceSettings};
+use launcher::{ExportFilter, Launcher};
 use rfd::FileDialog;
 use serde::{Deserialize, Serialize};
-use tokio::process::Command;
 use tokio::runtime::Runtime;
 use tokio::sync::mpsc::{self, UnboundedSender};
 use winit::application::ApplicationHandler;
@@ -21,6 +21,12 @@ mod config;
 mod launcher;
 mod util;
 mod minecraft;
+mod secrets;
+
+/// Actions that read or write account tokens and so need the secret store unlocked first.
+const SECRET_ACTIONS: [&str; 9] = ["add_server_login", "add_server", "upload_skin", "upload_cape", "set_skin_model", "run_server_instance", "run_microsoft_instance", "microsoft_login", "microsoft_poll"];
+
+const SKINS_UNSUPPORTED: &str = "Skins and capes can't be changed from the launcher for Yggdrasil servers";
 
 static SENDER: Mutex<Option<UnboundedSender<(String, Option<UIMessage>, RequestAsyncResponder)>>> = Mutex::new(None);
 
@@ -29,6 +35,22 @@ struct UIMessage {
     params: Vec<String>
 }
 
+/// Sends the user back to the login form of an account whose session could not be refreshed.
+fn relogin_message(launcher: &Launcher, username: &str, domain: &str, msg: String) -> UIMessage {
+    let server = launcher.find_credentials(username, domain);
+    let address = server.map(|s| s.address()).unwrap_or_else(|| domain.to_string());
+    let api_root = match server.map(|s| &s.auth) {
+        Some(AuthBackend::Yggdrasil { api_root }) => api_root.clone(),
+        _ => String::new()
+    };
+    UIMessage { params: vec!["relogin".to_string(), address, username.to_string(), msg, api_root] }
+}
+
+/// Skins and capes go through the XCraft session server API, which Yggdrasil servers don't have.
+fn skins_unsupported(launcher: &Launcher, username: &str, domain: &str) -> bool {
+    launcher.find_credentials(username, domain).is_some_and(|s| matches!(s.auth, AuthBackend::Yggdrasil { .. }))
+}
+
 #[derive(Default)]
 struct App {
   window: Option<Window>,
@@ -89,9 +111,13 @@ async fn main() {
         loop {
             if let Some((ui_action, params, responder)) = receiver.recv().await {
                 let ui_action = &ui_action[16..];
+                if SECRET_ACTIONS.contains(&ui_action) && launcher.secrets_locked() {
+                    responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["unlock_secrets".to_string(), launcher.config.credentials_path().exists().to_string()] }).unwrap()));
+                    continue;
+                }
                 match ui_action {
                     "github" => {
-                        Command::new("cmd").args(["/C", "start", "https://github.com/alterdekim/XCraft"]).spawn().unwrap();
+                        let _ = util::open_in_shell("https://github.com/alterdekim/XCraft");
                     },
                     "jquery" => responder.respond(Response::new(include_bytes!("js/jquery.js"))),
                     "skinview3d" => responder.respond(Response::new(include_bytes!("js/skinview3d.js"))),
@@ -113,14 +139,14 @@ async fn main() {
                             if !launcher.is_config_exist() {
                                 responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["show_login".to_string()] }).unwrap()))
                             } else {
-                                launcher.load_config();
+                                launcher.load_config().await;
                                 responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["show_add".to_string(), "sidebar_on".to_string()] }).unwrap()))
                             }
                         }
                     }
                     "sign_up" => {
                         let user_name = params.as_ref().unwrap().params.first().unwrap();
-                        launcher.init_config(user_name.to_string());
+                        launcher.init_config(user_name.to_string()).await;
                         responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["show_add".to_string(), "sidebar_on".to_string()] }).unwrap()));
                     }
                     "fetch_official_versions" => {
@@ -136,11 +162,12 @@ async fn main() {
                             responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: Vec::new() }).unwrap()));
                         }
                     }
-                    "import_multimc" => {
-                        if let Some(instance_path) = FileDialog::new().add_filter("Archive", &["zip"]).pick_file() {
+                    "import_pack" => {
+                        if let Some(pack_path) = FileDialog::new().add_filter("Modpack", &["zip", "mrpack"]).pick_file() {
                             responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["show_loading".to_string(), "sidebar_off".to_string()] }).unwrap()));
-                            if let Err(e) = launcher.import_multimc(instance_path, sx.clone()).await {
+                            if let Err(e) = launcher.import_pack(pack_path, sx.clone()).await {
                                 println!("Error: {}", e);
+                                let _ = sx.send((100, ["_", &e.to_string()].concat()));
                             }
                         }
                     }
@@ -161,6 +188,39 @@ async fn main() {
                             }
                         }
                     }
+                    "fetch_loader_versions" => {
+                        let params = &params.unwrap().params;
+                        let meta_url = match params[0].as_str() {
+                            "fabric" => Some(crate::minecraft::loaders::FABRIC_META_URL),
+                            "quilt" => Some(crate::minecraft::loaders::QUILT_META_URL),
+                            _ => None
+                        };
+                        let versions = match (params[0].as_str(), meta_url) {
+                            (_, Some(meta_url)) => crate::minecraft::loaders::fetch_loader_versions(meta_url, &params[1]).await
+                                .map(|mut v| {
+                                    // Stable builds first so the default pick isn't a beta
+                                    v.sort_by_key(|l| !l.stable);
+                                    v.into_iter().map(|l| l.version).collect::<Vec<String>>()
+                                }),
+                            ("neoforge", None) => crate::minecraft::loaders::fetch_neoforge_versions(&params[1]).await,
+                            _ => Ok(Vec::new())
+                        };
+                        match versions {
+                            Ok(versions) => responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: [ vec!["set_loader_versions".to_string()], versions ].concat() }).unwrap())),
+                            Err(e) => {
+                                println!("Error: {}", e);
+                                responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["set_loader_versions".to_string()] }).unwrap()));
+                            }
+                        }
+                    }
+                    "download_loader" => {
+                        let params = &params.unwrap().params;
+                        responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["show_loading".to_string(), "sidebar_off".to_string()] }).unwrap()));
+                        if let Err(e) = launcher.new_loader_instance(&params[0], &params[1], &params[2], sx.clone()).await {
+                            println!("Error: {}", e);
+                            let _ = sx.send((100, ["_", &e.to_string()].concat()));
+                        }
+                    }
                     "fetch_instances_list" => {
                         let resp = launcher.get_instances_list();
                         let mut v: Vec<String> = Vec::new();
@@ -177,7 +237,7 @@ async fn main() {
                         let params = params.unwrap().params;
                         let nickname = params[0].clone();
                         let domain = params[1].clone();
-                        if let Some(server) = launcher.find_credentials(&nickname, &domain) {
+                        if let Some(server) = launcher.find_credentials(&nickname, &domain).filter(|_| !skins_unsupported(&launcher, &nickname, &domain)) {
                             let resp = util::get_image(&["http", if launcher.config.allow_http { "" } else { "s" }, "://", &domain, ":", &server.session_server_port.to_string(), "/api/skin/s", &server.credentials.uuid].concat()).await;
                             if let Ok(resp) = resp {
                                 responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["get_skin".to_string(), resp] }).unwrap()));
@@ -188,7 +248,7 @@ async fn main() {
                         let params = params.unwrap().params;
                         let nickname = params[0].clone();
                         let domain = params[1].clone();
-                        if let Some(server) = launcher.find_credentials(&nickname, &domain) {
+                        if let Some(server) = launcher.find_credentials(&nickname, &domain).filter(|_| !skins_unsupported(&launcher, &nickname, &domain)) {
                             let resp = util::get_image(&["http", if launcher.config.allow_http { "" } else { "s" }, "://", &domain, ":", &server.session_server_port.to_string(), "/api/cape/a", &server.credentials.uuid].concat()).await;
                             if let Ok(resp) = resp {
                                 responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["get_cape".to_string(), resp] }).unwrap()));
@@ -197,10 +257,14 @@ async fn main() {
                     }
                     "upload_skin" => {
                         let params = params.unwrap().params;
-                        if let Some(server) = launcher.find_credentials(&params[0], &params[1]) {
+                        if skins_unsupported(&launcher, &params[0], &params[1]) {
+                            responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["add_server_response".to_string(), String::new(), SKINS_UNSUPPORTED.to_string()] }).unwrap()));
+                        } else if let Err(e) = launcher.refresh_session(&params[0], &params[1]).await {
+                            responder.respond(Response::new(serde_json::to_vec(&relogin_message(&launcher, &params[0], &params[1], e.to_string())).unwrap()));
+                        } else if let Some(server) = launcher.find_credentials(&params[0], &params[1]) {
                             if let Some(skin_path) = FileDialog::new().add_filter("Images", &["png"]).pick_file() {
                                 
-                                let msg = launcher.upload_skin(skin_path, &server.credentials.uuid, &server.credentials.password, &[if launcher.config.allow_http {"http"} else {"https"}, "://", &server.domain, ":", &server.session_server_port.to_string(), "/api/upload"].concat()).await;
+                                let msg = launcher.upload_skin(skin_path, &server.credentials.uuid, &server.credentials.tokens.access_token, &[if launcher.config.allow_http {"http"} else {"https"}, "://", &server.domain, ":", &server.session_server_port.to_string(), "/api/upload"].concat()).await;
                                 match msg {
                                     Ok(msg ) => {
                                         responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["add_server_response".to_string(), String::new(), msg] }).unwrap()));
@@ -214,10 +278,14 @@ async fn main() {
                     }
                     "upload_cape" => {
                         let params = params.unwrap().params;
-                        if let Some(server) = launcher.find_credentials(&params[0], &params[1]) {
+                        if skins_unsupported(&launcher, &params[0], &params[1]) {
+                            responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["add_server_response".to_string(), String::new(), SKINS_UNSUPPORTED.to_string()] }).unwrap()));
+                        } else if let Err(e) = launcher.refresh_session(&params[0], &params[1]).await {
+                            responder.respond(Response::new(serde_json::to_vec(&relogin_message(&launcher, &params[0], &params[1], e.to_string())).unwrap()));
+                        } else if let Some(server) = launcher.find_credentials(&params[0], &params[1]) {
                             if let Some(skin_path) = FileDialog::new().add_filter("Images", &["png"]).pick_file() {
                                 
-                                let msg = launcher.upload_cape(skin_path, &server.credentials.uuid, &server.credentials.password, &[if launcher.config.allow_http {"http"} else {"https"}, "://", &server.domain, ":", &server.session_server_port.to_string(), "/api/upload_cape"].concat()).await;
+                                let msg = launcher.upload_cape(skin_path, &server.credentials.uuid, &server.credentials.tokens.access_token, &[if launcher.config.allow_http {"http"} else {"https"}, "://", &server.domain, ":", &server.session_server_port.to_string(), "/api/upload_cape"].concat()).await;
                                 if let Ok(msg) = msg {
                                     responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["add_server_response".to_string(), String::new(), msg] }).unwrap()));
                                 } else {
@@ -228,8 +296,12 @@ async fn main() {
                     }
                     "set_skin_model" => {
                         let params = params.unwrap().params;
-                        if let Some(server) = launcher.find_credentials(&params[0], &params[1]) {
-                            let msg = launcher.set_skin_model(params[2].parse().unwrap(), &server.credentials.uuid, &server.credentials.password, &[if launcher.config.allow_http {"http"} else {"https"}, "://", &server.domain, ":", &server.session_server_port.to_string(), "/api/set_model"].concat()).await;
+                        if skins_unsupported(&launcher, &params[0], &params[1]) {
+                            responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["add_server_response".to_string(), String::new(), SKINS_UNSUPPORTED.to_string()] }).unwrap()));
+                        } else if let Err(e) = launcher.refresh_session(&params[0], &params[1]).await {
+                            responder.respond(Response::new(serde_json::to_vec(&relogin_message(&launcher, &params[0], &params[1], e.to_string())).unwrap()));
+                        } else if let Some(server) = launcher.find_credentials(&params[0], &params[1]) {
+                            let msg = launcher.set_skin_model(params[2].parse().unwrap(), &server.credentials.uuid, &server.credentials.tokens.access_token, &[if launcher.config.allow_http {"http"} else {"https"}, "://", &server.domain, ":", &server.session_server_port.to_string(), "/api/set_model"].concat()).await;
                             if let Ok(msg) = msg {
                                 responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["add_server_response".to_string(), String::new(), msg] }).unwrap()));
                             } else {
@@ -238,12 +310,11 @@ async fn main() {
                         }
                     }
                     "fetch_credentials_list" => {
-                        let resp = launcher.get_servers_list().await;
                         let mut v: Vec<String> = Vec::new();
                         v.push("fetch_credentials_list".to_string());
-                        for (domain, nickname, _image) in resp {
-                            v.push(domain);
-                            v.push(nickname);
+                        for server in launcher.config.servers() {
+                            v.push(server.domain.clone());
+                            v.push(server.credentials.username.clone());
                         }
                         responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: v }).unwrap()));
                     }
@@ -251,10 +322,21 @@ async fn main() {
                         let resp = launcher.get_servers_list().await;
                         let mut v: Vec<String> = Vec::new();
                         v.push("fetch_servers_list".to_string());
-                        for (domain, nickname, image) in resp {
+                        for (domain, nickname, icon, status) in resp {
                             v.push(domain);
                             v.push(nickname);
-                            v.push(image.unwrap_or(String::new()));
+                            v.push(icon.unwrap_or_default());
+                            match status {
+                                Some(status) => v.extend([
+                                    status.motd,
+                                    format!("{} (protocol {})", status.version, status.protocol),
+                                    status.online.to_string(),
+                                    status.max.to_string(),
+                                    status.latency.as_millis().to_string(),
+                                    status.players.join(", ")
+                                ]),
+                                None => v.extend(std::iter::repeat_n(String::new(), 6))
+                            }
                         }
 
                         responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: v }).unwrap()));
@@ -277,29 +359,84 @@ async fn main() {
                         let instance_name = params.unwrap().params[0].clone();
                         logs_rec.close();
                         (lx, logs_rec) = mpsc::unbounded_channel();
-                        launcher.launch_instance(instance_name, lx.clone(), None).await;
+                        launcher.launch_instance(instance_name, lx.clone(), None, None).await;
                     }
                     "run_server_instance" => {
                         let params = params.unwrap().params;
                         let instance_name = params[0].clone();
                         let domain = params[1].clone();
                         let nickname = params[2].clone();
+                        if let Err(e) = launcher.refresh_session(&nickname, &domain).await {
+                            responder.respond(Response::new(serde_json::to_vec(&relogin_message(&launcher, &nickname, &domain, e.to_string())).unwrap()));
+                            continue;
+                        }
                         logs_rec.close();
                         (lx, logs_rec) = mpsc::unbounded_channel();
                         let s = launcher.config.servers().iter().find(|s| s.domain == domain && s.credentials.username == nickname);
-                        launcher.launch_instance(instance_name, lx.clone(), s).await;
+                        launcher.launch_instance(instance_name, lx.clone(), s, None).await;
+                    }
+                    "run_microsoft_instance" => {
+                        let params = params.unwrap().params;
+                        let instance_name = params[0].clone();
+                        let uuid = params[1].clone();
+                        if let Err(e) = launcher.refresh_microsoft_account(&uuid).await {
+                            responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["show_popup".to_string(), e.to_string()] }).unwrap()));
+                            continue;
+                        }
+                        logs_rec.close();
+                        (lx, logs_rec) = mpsc::unbounded_channel();
+                        let account = launcher.config.microsoft_accounts().iter().find(|a| a.uuid == uuid);
+                        launcher.launch_instance(instance_name, lx.clone(), None, account).await;
+                    }
+                    "fetch_microsoft_accounts" => {
+                        let mut v: Vec<String> = Vec::new();
+                        v.push("set_microsoft_accounts".to_string());
+                        for account in launcher.config.microsoft_accounts() {
+                            v.push(account.uuid.clone());
+                            v.push(account.username.clone());
+                        }
+                        responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: v }).unwrap()));
+                    }
+                    "microsoft_login" => {
+                        match launcher.start_microsoft_login().await {
+                            Ok((user_code, verification_uri, interval)) => {
+                                let _ = util::open_in_shell(&verification_uri);
+                                responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["microsoft_code".to_string(), user_code, verification_uri, interval.to_string()] }).unwrap()));
+                            }
+                            Err(e) => responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["show_popup".to_string(), e.to_string()] }).unwrap()))
+                        }
+                    }
+                    "microsoft_poll" => {
+                        match launcher.poll_microsoft_login().await {
+                            Ok(None) => responder.respond(Response::new(vec![])),
+                            Ok(Some(username)) => responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["microsoft_done".to_string(), ["Signed in as ", &username].concat()] }).unwrap())),
+                            Err(e) => responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["microsoft_done".to_string(), e.to_string()] }).unwrap()))
+                        }
                     }
                     "locate_java" => {
-                        if let Ok(java_path) = java_locator::locate_file("java.exe") {
+                        let java_exe = if cfg!(windows) { "java.exe" } else { "java" };
+                        if let Ok(java_dir) = java_locator::locate_file(java_exe) {
+                            let java_path = std::path::Path::new(&java_dir).join(java_exe).to_str().unwrap().to_string();
                             launcher.config.java_path = java_path.clone();
-                            responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["locate_java".to_string(), [&java_path, "java.exe"].join("\\")] }).unwrap()));
+                            responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["locate_java".to_string(), java_path] }).unwrap()));
                         } else {
                             // todo: implement error notifications
                         }
                     }
+                    "unlock_secrets" => {
+                        let passphrase = params.unwrap().params[0].clone();
+                        let msg = match launcher.unlock_secrets(&passphrase) {
+                            Ok(()) => "Credentials unlocked, please try again".to_string(),
+                            Err(e) => e.to_string()
+                        };
+                        responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["show_popup".to_string(), msg] }).unwrap()));
+                    }
                     "add_server_login" => {
                         let params = &params.unwrap().params;
-                        let (status, msg) = launcher.login_user_server(params[0].clone(), params[1].clone(), params[2].clone()).await;
+                        let (status, msg) = match params.get(3).filter(|api_root| !api_root.is_empty()) {
+                            Some(api_root) => launcher.login_yggdrasil_server(params[0].clone(), api_root.clone(), params[1].clone(), params[2].clone()).await,
+                            None => launcher.login_user_server(params[0].clone(), params[1].clone(), params[2].clone()).await
+                        };
                         responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["add_server_response".to_string(), status.to_string(), msg.to_string()] }).unwrap()));
                     }
                     "add_server" => {
@@ -307,8 +444,91 @@ async fn main() {
                         let (status, msg) = launcher.register_user_server(params[0].clone(), params[1].clone(), params[2].clone()).await;
                         responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["add_server_response".to_string(), status.to_string(), msg.to_string()] }).unwrap()));
                     }
+                    "fetch_instance_settings" => {
+                        let instance_name = params.unwrap().params[0].clone();
+                        let settings = launcher.load_instance_settings(&instance_name);
+                        responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec![
+                            "instance_settings".to_string(),
+                            instance_name,
+                            settings.java_path.unwrap_or_default(),
+                            settings.min_memory.map(|m| m.to_string()).unwrap_or_default(),
+                            settings.max_memory.map(|m| m.to_string()).unwrap_or_default(),
+                            settings.jvm_args.join(" "),
+                            settings.game_args.join(" "),
+                            settings.window_width.map(|w| w.to_string()).unwrap_or_default(),
+                            settings.window_height.map(|h| h.to_string()).unwrap_or_default(),
+                            settings.fullscreen.to_string()
+                        ] }).unwrap()));
+                    }
+                    "update_instance_settings" => {
+                        let params = &params.unwrap().params;
+                        let settings = InstanceSettings {
+                            java_path: Some(params[1].clone()).filter(|p| !p.is_empty()),
+                            min_memory: params[2].parse().ok(),
+                            max_memory: params[3].parse().ok(),
+                            jvm_args: params[4].split_whitespace().map(|a| a.to_string()).collect(),
+                            game_args: params[5].split_whitespace().map(|a| a.to_string()).collect(),
+                            window_width: params[6].parse().ok(),
+                            window_height: params[7].parse().ok(),
+                            fullscreen: params[8].parse().unwrap_or_default()
+                        };
+                        if let Err(e) = launcher.save_instance_settings(&params[0], &settings) {
+                            println!("Error: {}", e);
+                        }
+                    }
+                    "rename_instance" | "duplicate_instance" | "delete_instance" => {
+                        let params = &params.unwrap().params;
+                        let result = match ui_action {
+                            "rename_instance" => launcher.rename_instance(&params[0], &params[1]),
+                            "duplicate_instance" => launcher.duplicate_instance(&params[0], &params[1], params[2] == "true"),
+                            _ => launcher.delete_instance(&params[0])
+                        };
+                        match result {
+                            Ok(()) => responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["show_instances".to_string()] }).unwrap())),
+                            Err(e) => {
+                                println!("Error: {}", e);
+                                responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["show_popup".to_string(), e.to_string()] }).unwrap()));
+                            }
+                        }
+                    }
+                    "repair_instance" => {
+                        let instance_name = params.unwrap().params[0].clone();
+                        responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["show_loading".to_string(), "sidebar_off".to_string()] }).unwrap()));
+                        if let Err(e) = launcher.repair_instance(&instance_name, sx.clone()).await {
+                            println!("Error: {}", e);
+                            let _ = sx.send((100, ["_", &e.to_string()].concat()));
+                        }
+                    }
+                    "scan_orphans" | "delete_orphans" => {
+                        let report = if ui_action == "scan_orphans" { launcher.find_orphans() } else { launcher.delete_orphans() };
+                        match report {
+                            Ok(report) => responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec![ui_action.to_string(), report.files.len().to_string(), report.bytes.to_string()] }).unwrap())),
+                            Err(e) => {
+                                println!("Error: {}", e);
+                                responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["show_popup".to_string(), e.to_string()] }).unwrap()));
+                            }
+                        }
+                    }
+                    "export_instance" => {
+                        let params = &params.unwrap().params;
+                        let extension = if params[1] == "mrpack" { "mrpack" } else { "zip" };
+                        let filter = ExportFilter {
+                            include: params.get(3).map(|p| p.split_whitespace().map(|p| p.to_string()).collect()).unwrap_or_default(),
+                            exclude: params[2].split_whitespace().map(|p| p.to_string()).collect()
+                        };
+                        if let Some(target) = FileDialog::new().add_filter("Modpack", &[extension]).set_file_name([&params[0], ".", extension].concat()).save_file() {
+                            let msg = match launcher.export_instance(&params[0], &target, &params[1], &filter) {
+                                Ok(()) => "Exported!".to_string(),
+                                Err(e) => {
+                                    println!("Error: {}", e);
+                                    format!("Export failed: {}", e)
+                                }
+                            };
+                            responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["show_popup".to_string(), msg] }).unwrap()));
+                        }
+                    }
                     "fetch_settings" => {
-                        responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["fetch_settings_response".to_string(), launcher.config.show_alpha.to_string(), launcher.config.show_beta.to_string(), launcher.config.show_snapshots.to_string(), launcher.config.java_path.clone(), launcher.config.ram_amount.to_string(), launcher.config.enable_blur.to_string(), launcher.config.allow_http.to_string()] }).unwrap()));
+                        responder.respond(Response::new(serde_json::to_vec(&UIMessage { params: vec!["fetch_settings_response".to_string(), launcher.config.show_alpha.to_string(), launcher.config.show_beta.to_string(), launcher.config.show_snapshots.to_string(), launcher.config.java_path.clone(), launcher.config.ram_amount.to_string(), launcher.config.enable_blur.to_string(), launcher.config.allow_http.to_string(), launcher.config.download_threads.to_string()] }).unwrap()));
                     }
                     "save_bg" => {
                         let params = &params.unwrap().params;
@@ -334,11 +554,12 @@ async fn main() {
                         launcher.config.show_snapshots = params[2].parse().unwrap();
                         launcher.config.enable_blur = params[5].parse().unwrap();
                         launcher.config.allow_http = params[6].parse().unwrap();
+                        launcher.config.download_threads = params[7].parse().unwrap_or(launcher.config.download_threads);
                         launcher.save_config();
                     }
                     "open_file" => {
                         let params = &params.unwrap().params;
-                        let _ = Command::new("cmd").args(["/C", "start", &params[0]]).spawn();
+                        let _ = util::open_in_shell(&params[0]);
                     }
                     "load_screenshots" => {
                         let screenshots = launcher.get_screenshots();
@@ -1,6 +1,6 @@
 
 pub mod versions {
-    use std::{error::Error, path::PathBuf};
+    use std::{collections::HashMap, error::Error, path::PathBuf};
 
     use serde::{Deserialize, Serialize};
 
@@ -35,82 +35,288 @@ pub mod versions {
         pub asset_index: Option<ConfigAssetIndex>,
         #[serde(rename = "mainClass")]
         pub main_class: String,
-        #[serde(rename = "minecraftArguments")]
-        pub minecraft_arguments: String,
+        #[serde(rename = "minecraftArguments", default, skip_serializing_if = "Option::is_none")]
+        pub minecraft_arguments: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub arguments: Option<VersionArguments>,
         pub downloads: Option<ConfigDownloads>,
         pub id: String,
         pub r#type: String,
-        pub libraries: Vec<VersionLibrary>
+        pub libraries: Vec<VersionLibrary>,
+        #[serde(rename = "javaVersion", default, skip_serializing_if = "Option::is_none")]
+        pub java_version: Option<JavaVersion>
+    }
+
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct JavaVersion {
+        pub component: String,
+        #[serde(rename = "majorVersion")]
+        pub major_version: u32
+    }
+
+    impl VersionConfig {
+        /// Game arguments for the host, from either `arguments.game` (1.13+) or the legacy `minecraftArguments` string.
+        pub fn game_arguments(&self, features: &[&str]) -> Vec<String> {
+            match &self.arguments {
+                Some(arguments) => arguments.game.iter().flat_map(|a| a.values(features)).collect(),
+                None => self.minecraft_arguments.as_deref().unwrap_or_default().split_whitespace().map(|a| a.to_string()).collect()
+            }
+        }
+
+        /// JVM arguments from `arguments.jvm`, or `None` for versions that leave them to the launcher.
+        pub fn jvm_arguments(&self, features: &[&str]) -> Option<Vec<String>> {
+            let arguments = self.arguments.as_ref().filter(|a| !a.jvm.is_empty())?;
+            Some(arguments.jvm.iter().flat_map(|a| a.values(features)).collect())
+        }
+
+        /// Mod loader merged into this version and its version, read back from the loader's own library.
+        pub fn loader(&self) -> Option<(&'static str, String)> {
+            self.libraries.iter().find_map(|library| {
+                let mut parts = library.name.split(':');
+                let (group, artifact, version) = (parts.next()?, parts.next()?, parts.next()?);
+                match (group, artifact) {
+                    ("net.fabricmc", "fabric-loader") => Some(("fabric", version.to_string())),
+                    ("org.quiltmc", "quilt-loader") => Some(("quilt", version.to_string())),
+                    ("net.neoforged", "neoforge") => Some(("neoforge", version.to_string())),
+                    ("net.neoforged", "forge") => Some(("neoforge", version.trim_start_matches(&[&self.id, "-"].concat()).to_string())),
+                    ("net.minecraftforge", "forge") => Some(("forge", version.trim_start_matches(&[&self.id, "-"].concat()).to_string())),
+                    _ => None
+                }
+            })
+        }
+
+        /// Applies a loader version JSON (Forge `version.json` and the like) on top of this one.
+        pub fn merge(&mut self, mut patch: VersionConfig) {
+            self.main_class = patch.main_class;
+            match (&mut self.arguments, patch.arguments.is_some()) {
+                // Legacy base under a modern patch: carry the old game args and the launcher's JVM defaults over
+                (None, true) => if let Some(minecraft_arguments) = self.minecraft_arguments.take() {
+                    self.arguments = Some(VersionArguments {
+                        game: legacy_arguments(&minecraft_arguments),
+                        jvm: legacy_arguments("-Djava.library.path=${natives_directory} -cp ${classpath}")
+                    });
+                },
+                // Legacy patch over a modern base: its `minecraftArguments` replace the game args as a whole
+                (Some(arguments), false) => if let Some(minecraft_arguments) = patch.minecraft_arguments.take() {
+                    arguments.game = legacy_arguments(&minecraft_arguments);
+                },
+                _ => {}
+            }
+            if patch.minecraft_arguments.is_some() {
+                self.minecraft_arguments = patch.minecraft_arguments;
+            }
+            if let Some(patch_arguments) = patch.arguments {
+                let arguments = self.arguments.get_or_insert_with(VersionArguments::default);
+                arguments.game.extend(patch_arguments.game);
+                arguments.jvm.extend(patch_arguments.jvm);
+            }
+            self.libraries.retain(|l| !patch.libraries.iter().any(|t| t.key() == l.key()));
+            self.libraries.extend(patch.libraries);
+        }
+    }
+
+    fn legacy_arguments(arguments: &str) -> Vec<Argument> {
+        arguments.split_whitespace().map(|a| Argument::Plain(a.to_string())).collect()
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default)]
+    pub struct VersionArguments {
+        #[serde(default)]
+        pub game: Vec<Argument>,
+        #[serde(default)]
+        pub jvm: Vec<Argument>
+    }
+
+    #[derive(Serialize, Deserialize, Clone)]
+    #[serde(untagged)]
+    pub enum Argument {
+        Plain(String),
+        Conditional { rules: Vec<Rule>, value: ArgumentValue }
+    }
+
+    #[derive(Serialize, Deserialize, Clone)]
+    #[serde(untagged)]
+    pub enum ArgumentValue {
+        Single(String),
+        Multiple(Vec<String>)
+    }
+
+    impl Argument {
+        pub fn values(&self, features: &[&str]) -> Vec<String> {
+            match self {
+                Argument::Plain(value) => vec![value.clone()],
+                Argument::Conditional { rules, value } if rules_allow(rules, features) => match value {
+                    ArgumentValue::Single(value) => vec![value.clone()],
+                    ArgumentValue::Multiple(values) => values.clone()
+                },
+                Argument::Conditional { .. } => Vec::new()
+            }
+        }
     }
 
     #[derive(Serialize, Deserialize, Clone)]
     pub struct VersionLibrary {
+        #[serde(default)]
         pub downloads: LibraryDownloads,
         pub name: String,
+        /// Maven repository root for loader libraries that come without a `downloads` block.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub url: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub sha1: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub size: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub rules: Option<Vec<Rule>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub natives: Option<HashMap<String, String>>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct Rule {
+        pub action: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub os: Option<RuleOs>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub features: Option<HashMap<String, bool>>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct RuleOs {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub name: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub arch: Option<String>,
+    }
+
+    /// Host OS as spelled in version JSON rules and natives maps.
+    pub fn os_name() -> &'static str {
+        match std::env::consts::OS {
+            "windows" => "windows",
+            "macos" => "osx",
+            _ => "linux"
+        }
+    }
+
+    /// Host architecture as spelled in version JSON rules.
+    pub fn os_arch() -> &'static str {
+        match std::env::consts::ARCH {
+            "x86" => "x86",
+            "aarch64" => "arm64",
+            arch => arch
+        }
+    }
+
+    impl Rule {
+        fn applies(&self, features: &[&str]) -> bool {
+            if let Some(os) = &self.os {
+                if os.name.as_ref().is_some_and(|name| name != os_name()) {
+                    return false;
+                }
+                if os.arch.as_ref().is_some_and(|arch| arch != os_arch()) {
+                    return false;
+                }
+            }
+            if let Some(required) = &self.features {
+                return required.iter().all(|(feature, enabled)| features.contains(&feature.as_str()) == *enabled);
+            }
+            true
+        }
+    }
+
+    /// Relative path of a Maven artifact, `group:artifact:version[:classifier][@extension]`.
+    pub fn maven_path(coordinates: &str) -> PathBuf {
+        let (coordinates, extension) = coordinates.split_once('@').unwrap_or((coordinates, "jar"));
+        let g = coordinates.split(":").collect::<Vec<&str>>();
+        let mut p = PathBuf::new();
+        for h in g[0].split(".") {
+            p.push(h);
+        }
+        let artifact_name = g.get(1).copied().unwrap_or_default();
+        let version = g.get(2).copied().unwrap_or_default();
+        p.push(artifact_name);
+        p.push(version);
+        let file_name = match g.get(3) {
+            Some(classifier) => [artifact_name, "-", version, "-", classifier].concat(),
+            None => [artifact_name, "-", version].concat()
+        };
+        p.push([&file_name, ".", extension].concat());
+        p
+    }
+
+    /// Evaluates rules the way the vanilla launcher does: the last matching rule wins and nothing is allowed by default.
+    pub fn rules_allow(rules: &[Rule], features: &[&str]) -> bool {
+        let mut allowed = false;
+        for rule in rules.iter().filter(|r| r.applies(features)) {
+            allowed = rule.action == "allow";
+        }
+        allowed
     }
 
     impl VersionLibrary {
-        pub fn to_pathbuf_path(&self) -> PathBuf {
-            let mut p = PathBuf::new();
-            let pkg = self.name.clone();
-            let g = pkg.split(":").collect::<Vec<&str>>();
-            let pkg_name = g[0];
-            let artifact_name = g[1];
-            let version = g[2];
-            let b = pkg_name.split(".").collect::<Vec<&str>>();
-            for h in b {
-                p.push(h);
-            }
-            p.push(artifact_name);
-            p.push(version);
-            p
+        pub fn is_allowed(&self) -> bool {
+            self.rules.as_ref().is_none_or(|rules| rules_allow(rules, &[]))
+        }
+
+        /// Natives classifier for the host, e.g. `natives-windows-64` for `natives-windows-${arch}`.
+        pub fn native_classifier(&self) -> Option<String> {
+            let classifier = self.natives.as_ref()?.get(os_name())?;
+            Some(classifier.replace("${arch}", if cfg!(target_pointer_width = "64") { "64" } else { "32" }))
+        }
+
+        pub fn native_download(&self) -> Option<&LibraryNatives> {
+            let classifier = self.native_classifier()?;
+            self.downloads.classifiers.as_ref()?.get(&classifier)
+        }
+
+        /// Maven coordinates without the version, so a loader can replace a library vanilla already ships.
+        pub fn key(&self) -> String {
+            let mut parts = self.name.split(':').collect::<Vec<&str>>();
+            if parts.len() > 2 {
+                parts.remove(2);
+            }
+            parts.join(":")
+        }
+
+        /// Where the main jar comes from: the `downloads` artifact, or the Maven repository in `url`.
+        pub fn artifact_url(&self) -> Option<String> {
+            if let Some(artifact) = &self.downloads.artifact {
+                return Some(artifact.url.clone());
+            }
+            let repository = self.url.as_ref()?;
+            let path = self.to_pathbuf_file(false).iter().map(|p| p.to_string_lossy().to_string()).collect::<Vec<String>>().join("/");
+            Some([repository.trim_end_matches('/'), "/", &path].concat())
         }
 
         pub fn to_pathbuf_file(&self, is_patched: bool) -> PathBuf {
-            let mut p = PathBuf::new();
-            let pkg = self.name.clone();
-            let g = pkg.split(":").collect::<Vec<&str>>();
-            let pkg_name = g[0];
-            let artifact_name = g[1];
-            let version = g[2];
-            let b = pkg_name.split(".").collect::<Vec<&str>>();
-            for h in b {
-                p.push(h);
-            }
-            p.push(artifact_name);
-            p.push(version);
-            if !is_patched {
-                p.push([artifact_name, "-", version, ".jar"].concat());
-            } else {
-                p.push([artifact_name, "-", version, "-patch.jar"].concat());
+            let mut p = maven_path(&self.name);
+            if is_patched {
+                let file_name = p.file_stem().unwrap_or_default().to_string_lossy().to_string();
+                p.set_file_name([&file_name, "-patch.jar"].concat());
             }
             p
         }
     }
 
-    #[derive(Serialize, Deserialize, Clone)]
-    pub struct LibraryClassifiers {
-        #[serde(rename = "natives-windows")]
-        pub natives: Option<LibraryNatives>
-    }
-
     #[derive(Serialize, Deserialize, Clone)]
     pub struct LibraryNatives {
         pub path: String,
+        /// Empty, like `size` zero, when unknown.
         pub sha1: String,
         pub size: u64,
         pub url: String
     }
 
-    #[derive(Serialize, Deserialize, Clone)]
+    #[derive(Serialize, Deserialize, Clone, Default)]
     pub struct LibraryDownloads {
         pub artifact: Option<LibraryArtifact>,
-        pub classifiers: Option<LibraryClassifiers>
+        pub classifiers: Option<HashMap<String, LibraryNatives>>
     }
 
     #[derive(Serialize, Deserialize, Clone)]
     pub struct LibraryArtifact {
         pub path: String,
+        /// Empty, like `size` zero, when unknown.
         pub sha1: String, 
         pub size: u64,
         pub url: String
@@ -165,146 +371,1526 @@ pub mod versions {
     pub async fn find_version_object(version: &str) -> Result<VersionConfig, Box<dyn Error + Send + Sync>> {
         let versions = fetch_versions_list().await?;
         let versions = versions.versions;
-        let version = versions.iter().find(|v| v.id == version).unwrap();
+        let version = versions.iter().find(|v| v.id == version).ok_or(format!("Unknown Minecraft version {}", version))?;
         let config = fetch_version_object(version).await?;
         Ok(config)
     }
 }
 
-pub mod session {
+pub mod loaders {
     use std::error::Error;
 
-    use serde::{Deserialize, Serialize};
+    use serde::Deserialize;
 
-    #[derive(Serialize)]
-    struct SignUpRequest {
-        username: String,
-        password: String,
-    }
+    use super::versions::VersionConfig;
 
-    pub enum SignUpResponse {
-        Registered(String),
-        BadCredentials,
-        UserAlreadyExists,
-        ServerError
-    }
+    pub const FABRIC_META_URL: &str = "https://meta.fabricmc.net/v2";
+    pub const QUILT_META_URL: &str = "https://meta.quiltmc.org/v3";
+    const FORGE_MAVEN_URL: &str = "https://maven.minecraftforge.net/net/minecraftforge/forge/";
+    const NEOFORGE_MAVEN_URL: &str = "https://maven.neoforged.net/releases/net/neoforged/neoforge/";
+    /// NeoForge for 1.20.1 still shipped as a Forge fork, under `forge` and versioned `1.20.1-47.1.*`.
+    const LEGACY_NEOFORGE_MAVEN_URL: &str = "https://maven.neoforged.net/releases/net/neoforged/forge/";
+    const LEGACY_NEOFORGE_GAME_VERSION: &str = "1.20.1";
 
     #[derive(Deserialize)]
-    struct ResponseUUID {
-        uuid: String
+    pub struct LoaderEntry {
+        pub loader: LoaderVersion
     }
 
-    pub async fn try_signup(server_domain: String, port: u16, username: String, password: String, allow_http: bool) -> Result<SignUpResponse, Box<dyn Error + Send + Sync>> {
-        let request = SignUpRequest { username, password };
-        let mut r = surf::post([if allow_http { "http://".to_string() } else { "https://".to_string() }, server_domain, ":".to_string(), port.to_string(), "/api/register".to_string()].concat())
-            .body_json(&request)
-            .unwrap()
-            .await?;
+    #[derive(Deserialize)]
+    pub struct LoaderVersion {
+        pub version: String,
+        /// Only Fabric meta reports this, Quilt builds come back as unstable.
+        #[serde(default)]
+        pub stable: bool
+    }
 
-        let b=  r.body_bytes().await.unwrap();
-        match r.status() {
-            surf::StatusCode::BadRequest => Ok(SignUpResponse::BadCredentials),
-            surf::StatusCode::Conflict => Ok(SignUpResponse::UserAlreadyExists),
-            surf::StatusCode::Ok => {
-                let response: ResponseUUID = serde_json::from_slice(&b).unwrap();
-                Ok(SignUpResponse::Registered(response.uuid))
-            },
-            _ => Ok(SignUpResponse::ServerError)
-        }
+    /// Loader versions available for a game version from a Fabric-compatible meta server, newest first.
+    pub async fn fetch_loader_versions(meta_url: &str, game_version: &str) -> Result<Vec<LoaderVersion>, Box<dyn Error + Send + Sync>> {
+        let mut r = surf::get([meta_url, "/versions/loader/", game_version].concat()).await?;
+        let resp = r.body_bytes().await?;
+        let entries: Vec<LoaderEntry> = serde_json::from_slice(&resp)?;
+        Ok(entries.into_iter().map(|e| e.loader).collect())
     }
 
-    pub async fn try_login(server_domain: String, port: u16, username: String, password: String, allow_http: bool) -> Result<SignUpResponse, Box<dyn Error + Send + Sync>> {
-        let request = SignUpRequest { username: username.clone(), password };
-        let mut r = surf::post([if allow_http { "http://".to_string() } else { "https://".to_string() }, server_domain, ":".to_string(), port.to_string(), "/api/login".to_string()].concat())
-            .body_json(&request)
-            .unwrap()
-            .await?;
+    /// Launcher profile to merge on top of the vanilla `client.json`.
+    pub async fn fetch_loader_profile(meta_url: &str, game_version: &str, loader_version: &str) -> Result<VersionConfig, Box<dyn Error + Send + Sync>> {
+        let mut r = surf::get([meta_url, "/versions/loader/", game_version, "/", loader_version, "/profile/json"].concat()).await?;
+        let resp = r.body_bytes().await?;
+        Ok(serde_json::from_slice(&resp)?)
+    }
 
-        let b=  r.body_bytes().await.unwrap();
+    pub fn forge_installer_url(game_version: &str, forge_version: &str) -> String {
+        let version = [game_version, "-", forge_version].concat();
+        [FORGE_MAVEN_URL, &version, "/forge-", &version, "-installer.jar"].concat()
+    }
 
-        match r.status() {
-            surf::StatusCode::BadRequest => Ok(SignUpResponse::BadCredentials),
-            surf::StatusCode::Conflict => Ok(SignUpResponse::UserAlreadyExists),
-            surf::StatusCode::Ok => {
-                let response: ResponseUUID = serde_json::from_slice(&b).unwrap();
-                Ok(SignUpResponse::Registered(response.uuid))
-            },
-            _ => Ok(SignUpResponse::ServerError)
+    pub fn neoforge_installer_url(game_version: &str, neoforge_version: &str) -> String {
+        if game_version == LEGACY_NEOFORGE_GAME_VERSION {
+            let version = [game_version, "-", neoforge_version.trim_start_matches(&[game_version, "-"].concat())].concat();
+            return [LEGACY_NEOFORGE_MAVEN_URL, &version, "/forge-", &version, "-installer.jar"].concat();
         }
+        [NEOFORGE_MAVEN_URL, neoforge_version, "/neoforge-", neoforge_version, "-installer.jar"].concat()
+    }
+
+    /// NeoForge versions for a game version, newest first. They drop the leading `1.`, so 1.21.1 maps to `21.1.*`,
+    /// except for 1.20.1 whose `1.20.1-47.1.*` builds are listed without the game version.
+    pub async fn fetch_neoforge_versions(game_version: &str) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let legacy = game_version == LEGACY_NEOFORGE_GAME_VERSION;
+        let (maven_url, prefix) = if legacy {
+            (LEGACY_NEOFORGE_MAVEN_URL, [game_version, "-"].concat())
+        } else {
+            let mut parts = game_version.trim_start_matches("1.").split('.');
+            (NEOFORGE_MAVEN_URL, [parts.next().unwrap_or_default(), ".", parts.next().unwrap_or("0"), "."].concat())
+        };
+
+        let metadata = surf::get([maven_url, "maven-metadata.xml"].concat()).recv_string().await?;
+        let mut versions: Vec<String> = metadata.split("<version>").skip(1)
+            .filter_map(|v| v.split("</version>").next())
+            .filter(|v| v.starts_with(&prefix))
+            .map(|v| if legacy { v.trim_start_matches(&prefix) } else { v }.to_string())
+            .collect();
+        versions.reverse();
+        Ok(versions)
     }
 }
 
-pub mod multimc {
+pub mod modrinth {
+    use std::collections::HashMap;
+
     use serde::{Deserialize, Serialize};
 
+    /// `modrinth.index.json` from an `.mrpack`.
     #[derive(Serialize, Deserialize)]
-    pub struct Pack {
-        pub components: Vec<Component>
+    pub struct Index {
+        #[serde(rename = "formatVersion", default)]
+        pub format_version: u32,
+        #[serde(default)]
+        pub game: String,
+        #[serde(rename = "versionId", default)]
+        pub version_id: String,
+        #[serde(default)]
+        pub name: String,
+        #[serde(default)]
+        pub files: Vec<PackFile>,
+        pub dependencies: HashMap<String, String>
     }
 
     #[derive(Serialize, Deserialize)]
-    pub struct Component {
-        #[serde(rename = "cachedName")]
-        pub cached_name: Option<String>,
-        pub version: String,
-        pub uid: String,
+    pub struct PackFile {
+        pub path: String,
+        #[serde(default)]
+        pub hashes: HashMap<String, String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub env: Option<HashMap<String, String>>,
+        #[serde(default)]
+        pub downloads: Vec<String>,
+        #[serde(rename = "fileSize", default, skip_serializing_if = "Option::is_none")]
+        pub file_size: Option<u64>
+    }
+
+    impl PackFile {
+        pub fn is_client_file(&self) -> bool {
+            self.env.as_ref().and_then(|env| env.get("client")).is_none_or(|client| client != "unsupported")
+        }
     }
+
+    impl Index {
+        /// Loader and its version, spelled the way `Launcher::fetch_loader_patch` expects.
+        pub fn loader(&self) -> Option<(&'static str, String)> {
+            LOADER_DEPENDENCIES.iter()
+                .find_map(|(dependency, loader)| self.dependencies.get(*dependency).map(|version| (*loader, version.clone())))
+        }
+    }
+
+    /// `.mrpack` dependency key -> loader name.
+    pub const LOADER_DEPENDENCIES: [(&str, &str); 4] = [("forge", "forge"), ("neoforge", "neoforge"), ("fabric-loader", "fabric"), ("quilt-loader", "quilt")];
 }
 
-pub mod server {
-    use std::error::Error;
+pub mod curseforge {
+    use std::{collections::HashMap, error::Error};
 
-    use base64::{prelude::BASE64_STANDARD, Engine};
-    use surf::StatusCode;
+    use serde::{Deserialize, Serialize};
 
-    pub async fn get_server_icon(server: &str, port: u16) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
-        let mut r = surf::get(["https://eu.mc-api.net/v3/server/favicon/", server,":", &port.to_string()].concat()).await?;
-        if r.status() != StatusCode::Ok { return Ok(None); }
-        let resp = r.body_bytes().await.unwrap();
-        Ok(Some(["data:image/png;base64,", &BASE64_STANDARD.encode(resp)].concat()))
+    /// `manifest.json` from a CurseForge modpack zip.
+    #[derive(Deserialize)]
+    pub struct Manifest {
+        pub minecraft: ManifestMinecraft,
+        #[serde(default)]
+        pub files: Vec<ManifestFile>,
+        #[serde(default = "default_overrides")]
+        pub overrides: String
+    }
+
+    fn default_overrides() -> String {
+        "overrides".to_string()
+    }
+
+    #[derive(Deserialize)]
+    pub struct ManifestMinecraft {
+        pub version: String,
+        #[serde(rename = "modLoaders", default)]
+        pub mod_loaders: Vec<ModLoader>
+    }
+
+    #[derive(Deserialize)]
+    pub struct ModLoader {
+        pub id: String,
+        #[serde(default)]
+        pub primary: bool
+    }
+
+    #[derive(Deserialize)]
+    pub struct ManifestFile {
+        #[serde(rename = "fileID")]
+        pub file_id: u64
+    }
+
+    impl Manifest {
+        /// Primary loader from ids like `forge-47.2.0` or `fabric-0.15.11`.
+        pub fn loader(&self) -> Option<(&'static str, String)> {
+            let loader = self.minecraft.mod_loaders.iter().find(|l| l.primary).or(self.minecraft.mod_loaders.first())?;
+            let (name, version) = loader.id.split_once('-')?;
+            let name = match name {
+                "forge" => "forge",
+                "neoforge" => "neoforge",
+                "fabric" => "fabric",
+                "quilt" => "quilt",
+                _ => return None
+            };
+            Some((name, version.to_string()))
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct ApiResponse<T> {
+        data: Vec<T>
+    }
+
+    #[derive(Deserialize)]
+    pub struct File {
+        pub id: u64,
+        #[serde(rename = "modId")]
+        pub mod_id: u64,
+        #[serde(rename = "fileName")]
+        pub file_name: String,
+        #[serde(rename = "downloadUrl")]
+        pub download_url: Option<String>,
+        #[serde(rename = "fileLength")]
+        pub file_length: u64,
+        #[serde(default)]
+        pub hashes: Vec<FileHash>
+    }
+
+    #[derive(Deserialize)]
+    pub struct FileHash {
+        pub value: String,
+        pub algo: u8
+    }
+
+    impl File {
+        /// Files whose authors disabled third-party downloads come without a url, but are still on the CDN.
+        pub fn download_url(&self) -> String {
+            self.download_url.clone().unwrap_or_else(|| format!("https://edge.forgecdn.net/files/{}/{}/{}", self.id / 1000, self.id % 1000, self.file_name))
+        }
+
+        pub fn sha1(&self) -> Option<&str> {
+            self.hashes.iter().find(|h| h.algo == 1).map(|h| h.value.as_str())
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct Mod {
+        id: u64,
+        #[serde(rename = "classId")]
+        class_id: Option<u64>
+    }
+
+    #[derive(Serialize)]
+    struct FilesRequest<'a> {
+        #[serde(rename = "fileIds")]
+        file_ids: &'a [u64]
+    }
+
+    #[derive(Serialize)]
+    struct ModsRequest<'a> {
+        #[serde(rename = "modIds")]
+        mod_ids: &'a [u64]
+    }
+
+    /// Game folder for a project class: resource packs and shaders, everything else is a mod.
+    pub fn class_folder(class_id: Option<u64>) -> &'static str {
+        match class_id {
+            Some(12) => "resourcepacks",
+            Some(6552) => "shaderpacks",
+            _ => "mods"
+        }
+    }
+
+    pub struct Api {
+        url: String,
+        key: Option<String>
+    }
+
+    impl Api {
+        pub fn new(url: &str, key: Option<&str>) -> Self {
+            Self { url: url.trim_end_matches('/').to_string(), key: key.map(|k| k.to_string()) }
+        }
+
+        async fn post<T: for<'de> Deserialize<'de>>(&self, path: &str, body: impl Serialize) -> Result<Vec<T>, Box<dyn Error + Send + Sync>> {
+            let mut request = surf::post([&self.url, path].concat()).body_json(&body)?;
+            if let Some(key) = &self.key {
+                request = request.header("x-api-key", key.as_str());
+            }
+            let mut r = request.await?;
+            if !r.status().is_success() {
+                return Err(format!("CurseForge API returned {}", r.status()).into());
+            }
+            let resp: ApiResponse<T> = serde_json::from_slice(&r.body_bytes().await?)?;
+            Ok(resp.data)
+        }
+
+        pub async fn fetch_files(&self, file_ids: &[u64]) -> Result<Vec<File>, Box<dyn Error + Send + Sync>> {
+            self.post("/v1/mods/files", FilesRequest { file_ids }).await
+        }
+
+        /// Project id -> class id.
+        pub async fn fetch_mod_classes(&self, mod_ids: &[u64]) -> Result<HashMap<u64, u64>, Box<dyn Error + Send + Sync>> {
+            let mods: Vec<Mod> = self.post("/v1/mods", ModsRequest { mod_ids }).await?;
+            Ok(mods.into_iter().filter_map(|m| Some((m.id, m.class_id?))).collect())
+        }
     }
 }
 
-pub mod assets {
-    use std::{collections::HashMap, error::Error, path::PathBuf};
-    use serde::{Deserialize, Serialize};
+pub mod forge {
+    use std::{collections::HashMap, error::Error, io::Read, path::{Path, PathBuf}};
 
-    #[derive(Serialize, Deserialize)]
-    pub struct SingleAsset {
-        pub hash: String,
-        pub sha1: Option<String>
+    use serde::Deserialize;
+    use tokio::{process::Command, sync::mpsc::UnboundedSender};
+
+    use super::versions::{maven_path, VersionLibrary};
+
+    #[derive(Deserialize)]
+    pub struct InstallProfile {
+        pub minecraft: String,
+        #[serde(default)]
+        pub data: HashMap<String, SidedData>,
+        #[serde(default)]
+        pub processors: Vec<Processor>,
+        #[serde(default)]
+        pub libraries: Vec<VersionLibrary>
     }
 
-    impl SingleAsset {
-        pub fn to_url(&self) -> String {
-            ["https://resources.download.minecraft.net/", &self.hash[..2], "/", &self.hash].concat()
+    #[derive(Deserialize)]
+    pub struct SidedData {
+        pub client: String
+    }
+
+    #[derive(Deserialize)]
+    pub struct Processor {
+        pub jar: String,
+        #[serde(default)]
+        pub classpath: Vec<String>,
+        #[serde(default)]
+        pub args: Vec<String>,
+        #[serde(default)]
+        pub outputs: HashMap<String, String>,
+        pub sides: Option<Vec<String>>
+    }
+
+    /// Client-side processors of a 1.13+ Forge-style installer, ready to run once their libraries are downloaded.
+    pub struct Installer {
+        pub libraries: Vec<VersionLibrary>,
+        processors: Vec<Processor>,
+        data: HashMap<String, String>,
+        libraries_dir: PathBuf,
+        work_dir: PathBuf
+    }
+
+    impl Installer {
+        /// Reads `install_profile.json` from an unpacked installer. `None` when there is nothing to run.
+        pub fn load(work_dir: &Path, installer_jar: &Path, libraries_dir: &Path) -> Result<Option<Self>, Box<dyn Error + Send + Sync>> {
+            let Ok(profile) = std::fs::read(work_dir.join("install_profile.json")) else { return Ok(None); };
+            let profile: InstallProfile = serde_json::from_slice(&profile)?;
+            let processors: Vec<Processor> = profile.processors.into_iter()
+                .filter(|p| p.sides.as_ref().is_none_or(|sides| sides.iter().any(|s| s == "client")))
+                .collect();
+            if processors.is_empty() {
+                return Ok(None);
+            }
+
+            let mut data = HashMap::from([
+                ("SIDE".to_string(), "client".to_string()),
+                ("MINECRAFT_VERSION".to_string(), profile.minecraft.clone()),
+                ("ROOT".to_string(), work_dir.to_string_lossy().to_string()),
+                ("INSTALLER".to_string(), installer_jar.to_string_lossy().to_string()),
+                ("LIBRARY_DIR".to_string(), libraries_dir.to_string_lossy().to_string())
+            ]);
+            for (key, value) in profile.data {
+                let value = value.client;
+                let resolved = if let Some(coordinates) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+                    libraries_dir.join(maven_path(coordinates)).to_string_lossy().to_string()
+                } else if let Some(literal) = value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+                    literal.to_string()
+                } else if let Some(file) = value.strip_prefix('/') {
+                    work_dir.join(file).to_string_lossy().to_string()
+                } else {
+                    value
+                };
+                data.insert(key, resolved);
+            }
+
+            Ok(Some(Self { libraries: profile.libraries, processors, data, libraries_dir: libraries_dir.to_path_buf(), work_dir: work_dir.to_path_buf() }))
         }
 
-        pub fn to_path(&self) -> PathBuf {
-            let mut p = PathBuf::new();
-            p.push("objects");
-            p.push(&self.hash[..2]);
-            p.push(&self.hash);
-            p
+        /// Expands `{DATA}` references and `[maven:coordinates]` in a processor argument.
+        fn resolve(&self, argument: &str) -> String {
+            if let Some(value) = argument.strip_prefix('{').and_then(|a| a.strip_suffix('}')).and_then(|key| self.data.get(key)) {
+                return value.clone();
+            }
+            if let Some(coordinates) = argument.strip_prefix('[').and_then(|a| a.strip_suffix(']')) {
+                return self.libraries_dir.join(maven_path(coordinates)).to_string_lossy().to_string();
+            }
+            argument.to_string()
         }
 
-        pub fn to_small_path(&self) -> PathBuf {
-            let mut p = PathBuf::new();
-            p.push("objects");
-            p.push(&self.hash[..2]);
-            p
+        /// Expected output files and their SHA-1s. Empty when the processor doesn't declare any.
+        fn outputs(&self, processor: &Processor) -> Vec<(PathBuf, String)> {
+            processor.outputs.iter().map(|(file, sha1)| (PathBuf::from(self.resolve(file)), self.resolve(sha1))).collect()
+        }
+
+        /// Runs every processor with `java`, skipping those whose outputs are already in place,
+        /// then removes the unpacked installer.
+        pub async fn run(mut self, java: &Path, minecraft_jar: &Path, sender: &UnboundedSender<(u8, String)>) -> Result<(), Box<dyn Error + Send + Sync>> {
+            self.data.insert("MINECRAFT_JAR".to_string(), minecraft_jar.to_string_lossy().to_string());
+            let result = self.run_processors(java, sender).await;
+            let _ = std::fs::remove_dir_all(&self.work_dir);
+            if let Some(installer) = self.data.get("INSTALLER") {
+                let _ = std::fs::remove_file(installer);
+            }
+            result
+        }
+
+        async fn run_processors(&self, java: &Path, sender: &UnboundedSender<(u8, String)>) -> Result<(), Box<dyn Error + Send + Sync>> {
+            let total = self.processors.len();
+            for (i, processor) in self.processors.iter().enumerate() {
+                let _ = sender.send(((i * 100 / total) as u8, "Running forge processors".to_string()));
+
+                let outputs = self.outputs(processor);
+                if !outputs.is_empty() && outputs.iter().all(|(file, sha1)| crate::util::is_file_valid(file, Some(sha1), None)) {
+                    continue;
+                }
+
+                let jar = self.libraries_dir.join(maven_path(&processor.jar));
+                let main_class = jar_main_class(&jar)?;
+                let mut classpath = vec![jar];
+                classpath.extend(processor.classpath.iter().map(|c| self.libraries_dir.join(maven_path(c))));
+
+                let output = Command::new(java)
+                    .arg("-cp")
+                    .arg(std::env::join_paths(classpath)?)
+                    .arg(&main_class)
+                    .args(processor.args.iter().map(|a| self.resolve(a)))
+                    .output()
+                    .await?;
+                if !output.status.success() {
+                    println!("{}", String::from_utf8_lossy(&output.stdout));
+                    println!("{}", String::from_utf8_lossy(&output.stderr));
+                    return Err(format!("Processor {} exited with {}", processor.jar, output.status).into());
+                }
+
+                for (file, sha1) in outputs {
+                    let actual = crate::util::file_sha1(&file)?;
+                    if !actual.eq_ignore_ascii_case(&sha1) {
+                        let _ = std::fs::remove_file(&file);
+                        return Err(format!("{}: expected sha1 {}, got {}", file.display(), sha1, actual).into());
+                    }
+                }
+            }
+            Ok(())
         }
     }
 
-    #[derive(Serialize, Deserialize)]
-    pub struct Assets {
-        pub objects: HashMap<String, SingleAsset>
+    fn jar_main_class(jar: &Path) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(jar)?)?;
+        let mut manifest = String::new();
+        archive.by_name("META-INF/MANIFEST.MF")?.read_to_string(&mut manifest)?;
+        manifest.lines()
+            .find_map(|l| l.strip_prefix("Main-Class:"))
+            .map(|c| c.trim().to_string())
+            .ok_or(format!("{} has no Main-Class", jar.display()).into())
     }
+}
 
-    pub async fn fetch_assets_list(url: &str) -> Result<Assets, Box<dyn Error + Send + Sync>> {
-        let mut r = surf::get(url).await?;
-        let resp = r.body_bytes().await.unwrap();
-        let resp: Assets = serde_json::from_slice(&resp)?;
-        Ok(resp)
+pub mod runtime {
+    use std::{collections::HashMap, error::Error};
+
+    use serde::Deserialize;
+
+    const RUNTIME_MANIFEST_URL: &str = "https://launchermeta.mojang.com/v1/products/java-runtime/2ec0cc96c44e5a76b9c8b7c39df7210883d12871/all.json";
+
+    /// Platform -> component (`jre-legacy`, `java-runtime-gamma`, ...) -> available builds.
+    pub type RuntimeManifest = HashMap<String, HashMap<String, Vec<RuntimeEntry>>>;
+
+    #[derive(Deserialize)]
+    pub struct RuntimeEntry {
+        pub manifest: RuntimeDownload,
+        pub version: RuntimeVersion
+    }
+
+    #[derive(Deserialize)]
+    pub struct RuntimeVersion {
+        pub name: String
     }
-}
\ No newline at end of file
+
+    #[derive(Deserialize)]
+    pub struct RuntimeDownload {
+        pub sha1: String,
+        pub size: u64,
+        pub url: String
+    }
+
+    #[derive(Deserialize)]
+    pub struct RuntimeFiles {
+        pub files: HashMap<String, RuntimeFile>
+    }
+
+    #[derive(Deserialize)]
+    pub struct RuntimeFile {
+        pub r#type: String,
+        #[serde(default)]
+        pub executable: bool,
+        pub downloads: Option<RuntimeFileDownloads>,
+        pub target: Option<String>
+    }
+
+    #[derive(Deserialize)]
+    pub struct RuntimeFileDownloads {
+        pub raw: RuntimeDownload
+    }
+
+    /// Name of the host platform in Mojang's runtime manifest, if Mojang ships runtimes for it.
+    pub fn runtime_platform() -> Option<&'static str> {
+        match (std::env::consts::OS, std::env::consts::ARCH) {
+            ("windows", "x86_64") => Some("windows-x64"),
+            ("windows", "x86") => Some("windows-x86"),
+            ("windows", "aarch64") => Some("windows-arm64"),
+            ("macos", "x86_64") => Some("mac-os"),
+            ("macos", "aarch64") => Some("mac-os-arm64"),
+            ("linux", "x86_64") => Some("linux"),
+            ("linux", "x86") => Some("linux-i386"),
+            _ => None
+        }
+    }
+
+    /// Path of the java executable relative to the runtime root.
+    pub fn java_executable_path() -> &'static str {
+        if cfg!(windows) {
+            "bin/javaw.exe"
+        } else if cfg!(target_os = "macos") {
+            "jre.bundle/Contents/Home/bin/java"
+        } else {
+            "bin/java"
+        }
+    }
+
+    pub async fn fetch_runtime_manifest() -> Result<RuntimeManifest, Box<dyn Error + Send + Sync>> {
+        let mut r = surf::get(RUNTIME_MANIFEST_URL).await?;
+        let resp = r.body_bytes().await?;
+        Ok(serde_json::from_slice(&resp)?)
+    }
+
+    pub async fn fetch_runtime_files(url: &str) -> Result<RuntimeFiles, Box<dyn Error + Send + Sync>> {
+        let mut r = surf::get(url).await?;
+        let resp = r.body_bytes().await?;
+        Ok(serde_json::from_slice(&resp)?)
+    }
+}
+
+pub mod session {
+    use std::error::Error;
+
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize)]
+    struct SignUpRequest {
+        username: String,
+        password: String,
+    }
+
+    #[derive(Serialize)]
+    struct RefreshRequest<'a> {
+        refresh_token: &'a str
+    }
+
+    pub enum SignUpResponse {
+        Registered(Session),
+        BadCredentials,
+        UserAlreadyExists,
+        ServerError
+    }
+
+    /// Tokens handed out by the session server in exchange for a password or a refresh token.
+    #[derive(Deserialize)]
+    pub struct Session {
+        pub uuid: String,
+        pub access_token: String,
+        pub refresh_token: String,
+        /// Lifetime of `access_token` in seconds.
+        pub expires_in: u64
+    }
+
+    fn api_url(server_domain: &str, port: u16, endpoint: &str, allow_http: bool) -> String {
+        [if allow_http { "http://" } else { "https://" }, server_domain, ":", &port.to_string(), "/api/", endpoint].concat()
+    }
+
+    /// Registers the account, then logs in with it so the password never has to be kept around.
+    pub async fn try_signup(server_domain: String, port: u16, username: String, password: String, allow_http: bool) -> Result<SignUpResponse, Box<dyn Error + Send + Sync>> {
+        let request = SignUpRequest { username: username.clone(), password: password.clone() };
+        let r = surf::post(api_url(&server_domain, port, "register", allow_http))
+            .body_json(&request)
+            .unwrap()
+            .await?;
+
+        match r.status() {
+            surf::StatusCode::BadRequest => Ok(SignUpResponse::BadCredentials),
+            surf::StatusCode::Conflict => Ok(SignUpResponse::UserAlreadyExists),
+            surf::StatusCode::Ok => try_login(server_domain, port, username, password, allow_http).await,
+            _ => Ok(SignUpResponse::ServerError)
+        }
+    }
+
+    pub async fn try_login(server_domain: String, port: u16, username: String, password: String, allow_http: bool) -> Result<SignUpResponse, Box<dyn Error + Send + Sync>> {
+        let request = SignUpRequest { username, password };
+        let mut r = surf::post(api_url(&server_domain, port, "login", allow_http))
+            .body_json(&request)
+            .unwrap()
+            .await?;
+
+        match r.status() {
+            surf::StatusCode::BadRequest | surf::StatusCode::Unauthorized => Ok(SignUpResponse::BadCredentials),
+            surf::StatusCode::Conflict => Ok(SignUpResponse::UserAlreadyExists),
+            surf::StatusCode::Ok => Ok(SignUpResponse::Registered(r.body_json().await?)),
+            _ => Ok(SignUpResponse::ServerError)
+        }
+    }
+
+    /// Trades a refresh token for a new session. `None` means the server rejected it and the user has to log in again.
+    pub async fn refresh(server_domain: &str, port: u16, refresh_token: &str, allow_http: bool) -> Result<Option<Session>, Box<dyn Error + Send + Sync>> {
+        let mut r = surf::post(api_url(server_domain, port, "refresh", allow_http))
+            .body_json(&RefreshRequest { refresh_token })
+            .unwrap()
+            .await?;
+
+        match r.status() {
+            surf::StatusCode::Ok => Ok(Some(r.body_json().await?)),
+            surf::StatusCode::BadRequest | surf::StatusCode::Unauthorized | surf::StatusCode::Forbidden => Ok(None),
+            status => Err(format!("Session server responded with {}", status).into())
+        }
+    }
+}
+
+/// Client side of the Yggdrasil auth protocol spoken by Mojang's legacy servers and authlib-injector compatible ones.
+pub mod yggdrasil {
+    use std::error::Error;
+
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct Profile {
+        pub id: String,
+        pub name: String
+    }
+
+    #[derive(Serialize)]
+    struct Agent {
+        name: &'static str,
+        version: u32
+    }
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct AuthenticateRequest<'a> {
+        agent: Agent,
+        username: &'a str,
+        password: &'a str,
+        client_token: &'a str,
+        request_user: bool
+    }
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct RefreshRequest<'a> {
+        access_token: &'a str,
+        client_token: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        selected_profile: Option<&'a Profile>
+    }
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct TokenRequest<'a> {
+        access_token: &'a str,
+        client_token: &'a str
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct AuthResponse {
+        pub access_token: String,
+        pub client_token: String,
+        #[serde(default)]
+        pub available_profiles: Vec<Profile>,
+        pub selected_profile: Option<Profile>
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct ErrorResponse {
+        error: String,
+        error_message: Option<String>
+    }
+
+    pub enum AuthResult {
+        Authenticated(AuthResponse),
+        /// The server's `errorMessage`, e.g. for a wrong password.
+        Rejected(String)
+    }
+
+    fn authserver_url(api_root: &str, endpoint: &str) -> String {
+        [api_root.trim_end_matches('/'), "/authserver/", endpoint].concat()
+    }
+
+    /// Session server base the patched authlib should talk to instead of Mojang's.
+    pub fn session_url(api_root: &str) -> String {
+        [api_root.trim_end_matches('/'), "/sessionserver/session/minecraft/"].concat()
+    }
+
+    pub async fn authenticate(api_root: &str, username: &str, password: &str, client_token: &str) -> Result<AuthResult, Box<dyn Error + Send + Sync>> {
+        let request = AuthenticateRequest { agent: Agent { name: "Minecraft", version: 1 }, username, password, client_token, request_user: false };
+        let mut r = surf::post(authserver_url(api_root, "authenticate"))
+            .body_json(&request)?
+            .await?;
+
+        if r.status().is_success() {
+            return Ok(AuthResult::Authenticated(r.body_json().await?));
+        }
+        let error: ErrorResponse = r.body_json().await?;
+        Ok(AuthResult::Rejected(error.error_message.unwrap_or(error.error)))
+    }
+
+    /// Issues a new access token, binding it to `selected_profile` if given. `None` means the token is no longer refreshable.
+    pub async fn refresh(api_root: &str, access_token: &str, client_token: &str, selected_profile: Option<&Profile>) -> Result<Option<AuthResponse>, Box<dyn Error + Send + Sync>> {
+        let request = RefreshRequest { access_token, client_token, selected_profile };
+        let mut r = surf::post(authserver_url(api_root, "refresh"))
+            .body_json(&request)?
+            .await?;
+
+        match r.status() {
+            s if s.is_success() => Ok(Some(r.body_json().await?)),
+            surf::StatusCode::Forbidden | surf::StatusCode::Unauthorized => Ok(None),
+            status => Err(format!("Auth server responded with {}", status).into())
+        }
+    }
+
+    pub async fn validate(api_root: &str, access_token: &str, client_token: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let r = surf::post(authserver_url(api_root, "validate"))
+            .body_json(&TokenRequest { access_token, client_token })?
+            .await?;
+
+        match r.status() {
+            s if s.is_success() => Ok(true),
+            surf::StatusCode::Forbidden | surf::StatusCode::Unauthorized => Ok(false),
+            status => Err(format!("Auth server responded with {}", status).into())
+        }
+    }
+
+    pub async fn invalidate(api_root: &str, access_token: &str, client_token: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        surf::post(authserver_url(api_root, "invalidate"))
+            .body_json(&TokenRequest { access_token, client_token })?
+            .await?;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::minecraft::stub::{serve, Request};
+        use serde_json::{json, Value};
+        use tokio::sync::mpsc;
+
+        #[test]
+        fn urls_ignore_trailing_slash() {
+            for api_root in ["https://auth.example.com/api", "https://auth.example.com/api/"] {
+                assert_eq!(authserver_url(api_root, "refresh"), "https://auth.example.com/api/authserver/refresh");
+                assert_eq!(session_url(api_root), "https://auth.example.com/api/sessionserver/session/minecraft/");
+            }
+        }
+
+        /// Serves a minimal auth server and reports every request it answered.
+        async fn mock_auth_server() -> (String, mpsc::UnboundedReceiver<Request>) {
+            let (url, requests) = serve(|request| {
+                let body: Value = serde_json::from_str(&request.body).unwrap();
+                match request.path.as_str() {
+                    "/api/authserver/authenticate" => ("200 OK", json!({
+                        "accessToken": "first", "clientToken": body["clientToken"],
+                        "availableProfiles": [{ "id": "0123", "name": "Steve" }]
+                    }).to_string()),
+                    "/api/authserver/refresh" => ("200 OK", json!({
+                        "accessToken": "second", "clientToken": body["clientToken"], "selectedProfile": body["selectedProfile"]
+                    }).to_string()),
+                    "/api/authserver/validate" if body["accessToken"] == "second" => ("204 No Content", String::new()),
+                    _ => ("403 Forbidden", json!({ "error": "ForbiddenOperationException" }).to_string())
+                }
+            }).await;
+            ([&url, "/api/"].concat(), requests)
+        }
+
+        #[tokio::test]
+        async fn authenticate_then_select_profile_on_refresh() {
+            let (api_root, mut requests) = mock_auth_server().await;
+
+            let AuthResult::Authenticated(auth) = authenticate(&api_root, "steve@example.com", "hunter2", "client").await.unwrap() else {
+                panic!("authentication was rejected");
+            };
+            assert!(auth.selected_profile.is_none());
+            let request = requests.recv().await.unwrap();
+            let body: Value = serde_json::from_str(&request.body).unwrap();
+            assert_eq!(request.path, "/api/authserver/authenticate");
+            assert_eq!(body["agent"]["name"], "Minecraft");
+            assert_eq!(body["username"], "steve@example.com");
+
+            let refreshed = refresh(&api_root, &auth.access_token, &auth.client_token, auth.available_profiles.first()).await.unwrap().unwrap();
+            assert_eq!(refreshed.selected_profile.unwrap().name, "Steve");
+            let request = requests.recv().await.unwrap();
+            let body: Value = serde_json::from_str(&request.body).unwrap();
+            assert_eq!(request.path, "/api/authserver/refresh");
+            assert_eq!(body["accessToken"], "first");
+            assert_eq!(body["selectedProfile"]["id"], "0123");
+
+            assert!(validate(&api_root, &refreshed.access_token, "client").await.unwrap());
+            assert!(!validate(&api_root, &auth.access_token, "client").await.unwrap());
+        }
+    }
+}
+
+/// Microsoft account sign-in: device code OAuth, then Xbox Live, XSTS and finally Minecraft services.
+pub mod microsoft {
+    use std::error::Error;
+
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    /// Every URL of the sign-in chain, overridable to run it against a local stub.
+    #[derive(Serialize, Deserialize, Clone)]
+    #[serde(default)]
+    pub struct Endpoints {
+        /// Azure application id; Microsoft doesn't hand out tokens without one.
+        pub client_id: Option<String>,
+        pub device_code_url: String,
+        pub token_url: String,
+        pub xbl_url: String,
+        pub xsts_url: String,
+        pub minecraft_login_url: String,
+        pub profile_url: String
+    }
+
+    impl Default for Endpoints {
+        fn default() -> Self {
+            Self {
+                client_id: None,
+                device_code_url: "https://login.microsoftonline.com/consumers/oauth2/v2.0/devicecode".to_string(),
+                token_url: "https://login.microsoftonline.com/consumers/oauth2/v2.0/token".to_string(),
+                xbl_url: "https://user.auth.xboxlive.com/user/authenticate".to_string(),
+                xsts_url: "https://xsts.auth.xboxlive.com/xsts/authorize".to_string(),
+                minecraft_login_url: "https://api.minecraftservices.com/authentication/login_with_xbox".to_string(),
+                profile_url: "https://api.minecraftservices.com/minecraft/profile".to_string()
+            }
+        }
+    }
+
+    const SCOPE: &str = "XboxLive.signin offline_access";
+
+    #[derive(Deserialize)]
+    pub struct DeviceCode {
+        pub device_code: String,
+        pub user_code: String,
+        pub verification_uri: String,
+        pub expires_in: u64,
+        #[serde(default = "default_interval")]
+        pub interval: u64
+    }
+
+    fn default_interval() -> u64 {
+        5
+    }
+
+    #[derive(Deserialize)]
+    struct OAuthToken {
+        access_token: String,
+        refresh_token: String
+    }
+
+    #[derive(Deserialize)]
+    struct OAuthError {
+        error: String,
+        error_description: Option<String>
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct XboxToken {
+        token: String,
+        display_claims: XboxClaims
+    }
+
+    #[derive(Deserialize)]
+    struct XboxClaims {
+        xui: Vec<XboxUser>
+    }
+
+    #[derive(Deserialize)]
+    struct XboxUser {
+        uhs: String
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct XstsError {
+        x_err: u64
+    }
+
+    #[derive(Deserialize)]
+    struct MinecraftToken {
+        access_token: String,
+        expires_in: u64
+    }
+
+    #[derive(Deserialize)]
+    struct Profile {
+        id: String,
+        name: String
+    }
+
+    /// Outcome of a finished sign-in or refresh.
+    pub struct Account {
+        pub uuid: String,
+        pub username: String,
+        pub access_token: String,
+        /// Lifetime of `access_token` in seconds.
+        pub expires_in: u64,
+        /// Microsoft refresh token to sign in again without the user.
+        pub refresh_token: String
+    }
+
+    fn client_id(endpoints: &Endpoints) -> Result<&str, Box<dyn Error + Send + Sync>> {
+        endpoints.client_id.as_deref().ok_or_else(|| "Set microsoft.client_id in config.toml to an Azure application id".into())
+    }
+
+    pub async fn request_device_code(endpoints: &Endpoints) -> Result<DeviceCode, Box<dyn Error + Send + Sync>> {
+        let mut r = surf::post(&endpoints.device_code_url)
+            .body(surf::Body::from_form(&[("client_id", client_id(endpoints)?), ("scope", SCOPE)])?)
+            .await?;
+        if !r.status().is_success() {
+            return Err(format!("Device code request failed with {}", r.status()).into());
+        }
+        Ok(r.body_json().await?)
+    }
+
+    /// Checks once whether the user has entered the code yet. `None` means they haven't.
+    pub async fn poll_device_code(endpoints: &Endpoints, device_code: &str) -> Result<Option<Account>, Box<dyn Error + Send + Sync>> {
+        let form = [("grant_type", "urn:ietf:params:oauth:grant-type:device_code"), ("client_id", client_id(endpoints)?), ("device_code", device_code)];
+        let mut r = surf::post(&endpoints.token_url)
+            .body(surf::Body::from_form(&form)?)
+            .await?;
+        if !r.status().is_success() {
+            let error: OAuthError = r.body_json().await?;
+            return match error.error.as_str() {
+                "authorization_pending" | "slow_down" => Ok(None),
+                _ => Err(error.error_description.unwrap_or(error.error).into())
+            };
+        }
+        let token: OAuthToken = r.body_json().await?;
+        Ok(Some(sign_in(endpoints, token).await?))
+    }
+
+    /// Signs in again with a refresh token from an earlier `Account`. `None` means the token was revoked or
+    /// has expired and the user has to sign in again.
+    pub async fn refresh(endpoints: &Endpoints, refresh_token: &str) -> Result<Option<Account>, Box<dyn Error + Send + Sync>> {
+        let form = [("grant_type", "refresh_token"), ("client_id", client_id(endpoints)?), ("refresh_token", refresh_token), ("scope", SCOPE)];
+        let mut r = surf::post(&endpoints.token_url)
+            .body(surf::Body::from_form(&form)?)
+            .await?;
+        if !r.status().is_success() {
+            let error: OAuthError = r.body_json().await?;
+            return match error.error.as_str() {
+                "invalid_grant" | "interaction_required" => Ok(None),
+                _ => Err(error.error_description.unwrap_or(error.error).into())
+            };
+        }
+        Ok(Some(sign_in(endpoints, r.body_json().await?).await?))
+    }
+
+    async fn sign_in(endpoints: &Endpoints, token: OAuthToken) -> Result<Account, Box<dyn Error + Send + Sync>> {
+        let xbl: XboxToken = surf::post(&endpoints.xbl_url)
+            .body_json(&json!({
+                "Properties": { "AuthMethod": "RPS", "SiteName": "user.auth.xboxlive.com", "RpsTicket": (["d=", &token.access_token].concat()) },
+                "RelyingParty": "http://auth.xboxlive.com",
+                "TokenType": "JWT"
+            }))?
+            .recv_json()
+            .await?;
+
+        let mut r = surf::post(&endpoints.xsts_url)
+            .body_json(&json!({
+                "Properties": { "SandboxId": "RETAIL", "UserTokens": [xbl.token] },
+                "RelyingParty": "rp://api.minecraftservices.com/",
+                "TokenType": "JWT"
+            }))?
+            .await?;
+        if r.status() == surf::StatusCode::Unauthorized {
+            let error: XstsError = r.body_json().await?;
+            return Err(match error.x_err {
+                2148916233 => "This Microsoft account has no Xbox profile yet".to_string(),
+                2148916235 => "Xbox Live is not available in your country".to_string(),
+                2148916236 | 2148916237 => "This account needs adult verification on the Xbox website".to_string(),
+                2148916238 => "This is a child account, add it to a Microsoft family first".to_string(),
+                code => format!("Xbox Live refused the sign in ({})", code)
+            }.into());
+        }
+        let xsts: XboxToken = r.body_json().await?;
+        let uhs = xsts.display_claims.xui.first().ok_or("Xbox Live returned no user hash")?.uhs.clone();
+
+        let minecraft: MinecraftToken = surf::post(&endpoints.minecraft_login_url)
+            .body_json(&json!({ "identityToken": (["XBL3.0 x=", &uhs, ";", &xsts.token].concat()) }))?
+            .recv_json()
+            .await?;
+
+        let mut r = surf::get(&endpoints.profile_url)
+            .header("Authorization", ["Bearer ", &minecraft.access_token].concat())
+            .await?;
+        if r.status() == surf::StatusCode::NotFound {
+            return Err("This Microsoft account doesn't own Minecraft".into());
+        }
+        let profile: Profile = r.body_json().await?;
+
+        Ok(Account {
+            uuid: profile.id,
+            username: profile.name,
+            access_token: minecraft.access_token,
+            expires_in: minecraft.expires_in,
+            refresh_token: token.refresh_token
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::minecraft::stub::{serve, Request};
+        use tokio::sync::mpsc;
+
+        /// Serves the whole sign-in chain. The first device code poll is still pending and only
+        /// the refresh token `first-refresh` is accepted.
+        async fn mock_microsoft() -> (Endpoints, mpsc::UnboundedReceiver<Request>) {
+            let mut polled = false;
+            let (url, requests) = serve(move |request| {
+                let body = request.body.as_str();
+                match request.path.as_str() {
+                    "/devicecode" => ("200 OK", json!({
+                        "device_code": "device", "user_code": "ABCD-EFGH", "verification_uri": "https://microsoft.com/link", "expires_in": 900
+                    }).to_string()),
+                    "/token" if body.contains("device_code=device") && !polled => {
+                        polled = true;
+                        ("400 Bad Request", json!({ "error": "authorization_pending" }).to_string())
+                    }
+                    "/token" if body.contains("device_code=device") || body.contains("refresh_token=first-refresh") => ("200 OK", json!({
+                        "access_token": "oauth", "refresh_token": if body.contains("device_code") { "first-refresh" } else { "second-refresh" }
+                    }).to_string()),
+                    "/token" => ("400 Bad Request", json!({ "error": "invalid_grant", "error_description": "Refresh token revoked" }).to_string()),
+                    "/xbl" if body.contains("d=oauth") => ("200 OK", json!({ "Token": "xbl", "DisplayClaims": { "xui": [{ "uhs": "hash" }] } }).to_string()),
+                    "/xsts" if body.contains("xbl") => ("200 OK", json!({ "Token": "xsts", "DisplayClaims": { "xui": [{ "uhs": "hash" }] } }).to_string()),
+                    "/login" if body.contains("XBL3.0 x=hash;xsts") => ("200 OK", json!({ "access_token": "minecraft", "expires_in": 86400 }).to_string()),
+                    "/profile" if request.headers.contains("Bearer minecraft") => ("200 OK", json!({ "id": "0123", "name": "Steve" }).to_string()),
+                    _ => ("401 Unauthorized", "{}".to_string())
+                }
+            }).await;
+            let endpoints = Endpoints {
+                client_id: Some("client".to_string()),
+                device_code_url: [&url, "/devicecode"].concat(),
+                token_url: [&url, "/token"].concat(),
+                xbl_url: [&url, "/xbl"].concat(),
+                xsts_url: [&url, "/xsts"].concat(),
+                minecraft_login_url: [&url, "/login"].concat(),
+                profile_url: [&url, "/profile"].concat()
+            };
+            (endpoints, requests)
+        }
+
+        #[tokio::test]
+        async fn device_code_sign_in_then_refresh() {
+            let (endpoints, mut requests) = mock_microsoft().await;
+
+            let code = request_device_code(&endpoints).await.unwrap();
+            assert_eq!(code.user_code, "ABCD-EFGH");
+            assert_eq!(code.interval, 5);
+            assert!(poll_device_code(&endpoints, &code.device_code).await.unwrap().is_none());
+
+            let account = poll_device_code(&endpoints, &code.device_code).await.unwrap().unwrap();
+            assert_eq!((account.uuid.as_str(), account.username.as_str()), ("0123", "Steve"));
+            assert_eq!((account.access_token.as_str(), account.expires_in), ("minecraft", 86400));
+            assert_eq!(account.refresh_token, "first-refresh");
+            let mut paths = Vec::new();
+            while let Ok(request) = requests.try_recv() {
+                paths.push(request.path);
+            }
+            assert_eq!(paths, ["/devicecode", "/token", "/token", "/xbl", "/xsts", "/login", "/profile"]);
+
+            let refreshed = refresh(&endpoints, &account.refresh_token).await.unwrap().unwrap();
+            assert_eq!(refreshed.refresh_token, "second-refresh");
+
+            assert!(refresh(&endpoints, &refreshed.refresh_token).await.unwrap().is_none());
+        }
+    }
+}
+
+pub mod multimc {
+    use std::collections::HashMap;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::versions::{Argument, VersionConfig, VersionLibrary};
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Pack {
+        #[serde(rename = "formatVersion", default)]
+        pub format_version: u32,
+        pub components: Vec<Component>
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Component {
+        #[serde(rename = "cachedName", default, skip_serializing_if = "Option::is_none")]
+        pub cached_name: Option<String>,
+        pub version: String,
+        pub uid: String,
+    }
+
+    /// Component override from `patches/<uid>.json`.
+    #[derive(Deserialize)]
+    pub struct Patch {
+        #[serde(rename = "mainClass")]
+        pub main_class: Option<String>,
+        #[serde(rename = "minecraftArguments")]
+        pub minecraft_arguments: Option<String>,
+        #[serde(default)]
+        pub libraries: Vec<VersionLibrary>,
+        #[serde(rename = "+libraries", default)]
+        pub extra_libraries: Vec<VersionLibrary>,
+        #[serde(rename = "+tweakers", default)]
+        pub tweakers: Vec<String>,
+        #[serde(rename = "+jvmArgs", default)]
+        pub jvm_args: Vec<String>
+    }
+
+    impl Patch {
+        /// Applies everything but `+jvmArgs`, which belong in the instance settings.
+        pub fn apply(&self, config: &mut VersionConfig) {
+            if let Some(main_class) = &self.main_class {
+                config.main_class = main_class.clone();
+            }
+            if let Some(minecraft_arguments) = &self.minecraft_arguments {
+                config.minecraft_arguments = Some(minecraft_arguments.clone());
+            }
+            let libraries = self.libraries.iter().chain(&self.extra_libraries).cloned().collect::<Vec<VersionLibrary>>();
+            config.libraries.retain(|l| !libraries.iter().any(|t| t.key() == l.key()));
+            config.libraries.extend(libraries);
+            for tweaker in &self.tweakers {
+                match &mut config.arguments {
+                    Some(arguments) => arguments.game.extend([Argument::Plain("--tweakClass".to_string()), Argument::Plain(tweaker.clone())]),
+                    None => {
+                        let arguments = config.minecraft_arguments.get_or_insert_with(String::new);
+                        arguments.push_str(&[" --tweakClass ", tweaker].concat());
+                    }
+                }
+            }
+        }
+    }
+
+    /// MultiMC component uid and display name of a loader.
+    pub fn loader_component(loader: &str) -> Option<(&'static str, &'static str)> {
+        match loader {
+            "forge" => Some(("net.minecraftforge", "Forge")),
+            "neoforge" => Some(("net.neoforged", "NeoForge")),
+            "fabric" => Some(("net.fabricmc.fabric-loader", "Fabric Loader")),
+            "quilt" => Some(("org.quiltmc.quilt-loader", "Quilt Loader")),
+            _ => None
+        }
+    }
+
+    /// Library (group, artifact) behind a component that only pins the version of something
+    /// the version or loader JSON already pulls in.
+    pub fn pinned_library(uid: &str) -> Option<(&'static str, &'static str)> {
+        match uid {
+            "org.lwjgl" => Some(("org.lwjgl.lwjgl", "lwjgl")),
+            "org.lwjgl3" => Some(("org.lwjgl", "lwjgl")),
+            "net.fabricmc.intermediary" => Some(("net.fabricmc", "intermediary")),
+            "org.quiltmc.hashed" => Some(("org.quiltmc", "hashed")),
+            _ => None
+        }
+    }
+
+    /// Moves the libraries behind a pinned component to the pinned version: every library of the same group
+    /// that shares the resolved version, e.g. all of LWJGL 2 including its natives. The checksums of the old
+    /// version no longer apply, so the moved jars are downloaded unverified.
+    pub fn apply_pinned(component: &Component, config: &mut VersionConfig) {
+        let Some((group, artifact)) = pinned_library(&component.uid) else { return; };
+        let Some(resolved) = config.libraries.iter()
+            .filter(|l| l.is_allowed())
+            .filter_map(|l| {
+                let mut parts = l.name.split(':');
+                (parts.next()? == group && parts.next()? == artifact).then_some(parts.next()?.to_string())
+            })
+            .find(|version| *version != component.version) else { return; };
+
+        let pinned = component.version.as_str();
+        let swap = |s: &str| s.replace(&["/", &resolved, "/"].concat(), &["/", pinned, "/"].concat())
+            .replace(&["-", &resolved].concat(), &["-", pinned].concat());
+        for library in &mut config.libraries {
+            let mut parts = library.name.split(':').collect::<Vec<&str>>();
+            if parts.len() < 3 || parts[0] != group || parts[2] != resolved {
+                continue;
+            }
+            parts[2] = pinned;
+            library.name = parts.join(":");
+            library.sha1 = None;
+            library.size = None;
+            let classifiers = library.downloads.classifiers.iter_mut().flat_map(|c| c.values_mut());
+            for (path, url, sha1, size) in library.downloads.artifact.iter_mut().map(|a| (&mut a.path, &mut a.url, &mut a.sha1, &mut a.size))
+                .chain(classifiers.map(|n| (&mut n.path, &mut n.url, &mut n.sha1, &mut n.size))) {
+                *path = swap(path);
+                *url = swap(url);
+                sha1.clear();
+                *size = 0;
+            }
+        }
+    }
+
+    /// Reads the `key=value` lines of `instance.cfg`, ignoring `[section]` headers.
+    pub fn parse_instance_cfg(data: &str) -> HashMap<String, String> {
+        data.lines()
+            .filter_map(|l| l.split_once('='))
+            .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+            .collect()
+    }
+}
+
+pub mod server {
+    use std::{error::Error, time::{Duration, Instant}};
+
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use serde::Deserialize;
+    use serde_json::Value;
+    use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream};
+
+    const PING_TIMEOUT: Duration = Duration::from_secs(5);
+    const FAVICON_PREFIX: &str = "data:image/png;base64,";
+    const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+    /// Status responses carry a base64 favicon, but nothing legitimate comes close to this.
+    const MAX_PACKET_SIZE: usize = 1 << 21;
+
+    pub struct ServerStatus {
+        pub motd: String,
+        pub version: String,
+        pub protocol: i32,
+        pub online: u32,
+        pub max: u32,
+        pub players: Vec<String>,
+        /// Always a `data:image/png;base64,` URL re-encoded from PNG bytes that decoded fine.
+        pub favicon: Option<String>,
+        pub latency: Duration
+    }
+
+    #[derive(Deserialize)]
+    struct StatusResponse {
+        version: Option<StatusVersion>,
+        players: Option<StatusPlayers>,
+        #[serde(default)]
+        description: Value,
+        favicon: Option<String>
+    }
+
+    #[derive(Deserialize)]
+    struct StatusVersion {
+        name: String,
+        protocol: i32
+    }
+
+    #[derive(Deserialize)]
+    struct StatusPlayers {
+        max: u32,
+        online: u32,
+        #[serde(default)]
+        sample: Vec<StatusPlayer>
+    }
+
+    #[derive(Deserialize)]
+    struct StatusPlayer {
+        name: String
+    }
+
+    /// PNG bytes of a status favicon, or `None` for anything but a base64 PNG data URL.
+    pub fn decode_favicon(favicon: &str) -> Option<Vec<u8>> {
+        // Some old servers wrap the base64 like MIME does
+        let data = favicon.strip_prefix(FAVICON_PREFIX)?.replace(['\n', '\r'], "");
+        BASE64_STANDARD.decode(data).ok().filter(|png| png.starts_with(PNG_SIGNATURE))
+    }
+
+    pub fn favicon_url(png: &[u8]) -> String {
+        [FAVICON_PREFIX, &BASE64_STANDARD.encode(png)].concat()
+    }
+
+    /// Pings a server with Server List Ping, falling back to the pre-1.7 `0xFE` ping for old servers.
+    /// Each attempt gets `PING_TIMEOUT` of its own.
+    pub async fn ping(server: &str, port: u16) -> Result<ServerStatus, Box<dyn Error + Send + Sync>> {
+        match tokio::time::timeout(PING_TIMEOUT, status(server, port)).await {
+            Ok(Ok(status)) => Ok(status),
+            _ => Ok(tokio::time::timeout(PING_TIMEOUT, legacy_status(server, port)).await??)
+        }
+    }
+
+    fn write_varint(buf: &mut Vec<u8>, value: i32) {
+        let mut value = value as u32;
+        loop {
+            if value & !0x7F == 0 {
+                buf.push(value as u8);
+                return;
+            }
+            buf.push((value & 0x7F) as u8 | 0x80);
+            value >>= 7;
+        }
+    }
+
+    async fn read_varint(stream: &mut TcpStream) -> Result<i32, Box<dyn Error + Send + Sync>> {
+        let mut value = 0u32;
+        for i in 0..5 {
+            let byte = stream.read_u8().await?;
+            value |= ((byte & 0x7F) as u32) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(value as i32);
+            }
+        }
+        Err("VarInt is too big".into())
+    }
+
+    async fn write_packet(stream: &mut TcpStream, id: i32, body: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut packet = Vec::new();
+        write_varint(&mut packet, id);
+        packet.extend_from_slice(body);
+        let mut framed = Vec::new();
+        write_varint(&mut framed, packet.len() as i32);
+        framed.extend(packet);
+        stream.write_all(&framed).await?;
+        Ok(())
+    }
+
+    /// Reads one packet, returning its id and body.
+    async fn read_packet(stream: &mut TcpStream) -> Result<(i32, Vec<u8>), Box<dyn Error + Send + Sync>> {
+        let length = read_varint(stream).await? as usize;
+        if length == 0 || length > MAX_PACKET_SIZE {
+            return Err(format!("Bad packet length {}", length).into());
+        }
+        let mut packet = vec![0; length];
+        stream.read_exact(&mut packet).await?;
+        let (id, read) = parse_varint(&packet)?;
+        Ok((id, packet.split_off(read)))
+    }
+
+    /// Decodes a VarInt at the start of `buf`, returning it with the number of bytes it took.
+    fn parse_varint(buf: &[u8]) -> Result<(i32, usize), Box<dyn Error + Send + Sync>> {
+        let mut value = 0u32;
+        for (i, byte) in buf.iter().take(5).enumerate() {
+            value |= ((byte & 0x7F) as u32) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok((value as i32, i + 1));
+            }
+        }
+        Err("Bad VarInt".into())
+    }
+
+    async fn status(server: &str, port: u16) -> Result<ServerStatus, Box<dyn Error + Send + Sync>> {
+        let mut stream = TcpStream::connect((server, port)).await?;
+
+        let mut handshake = Vec::new();
+        // -1 asks the server to report its own protocol version
+        write_varint(&mut handshake, -1);
+        write_varint(&mut handshake, server.len() as i32);
+        handshake.extend_from_slice(server.as_bytes());
+        handshake.extend_from_slice(&port.to_be_bytes());
+        write_varint(&mut handshake, 1);
+        write_packet(&mut stream, 0x00, &handshake).await?;
+        write_packet(&mut stream, 0x00, &[]).await?;
+
+        let (id, body) = read_packet(&mut stream).await?;
+        if id != 0x00 {
+            return Err(format!("Unexpected status packet {}", id).into());
+        }
+        let (json_length, start) = parse_varint(&body)?;
+        let json = body.get(start..start + json_length as usize).ok_or("Truncated status response")?;
+        let response: StatusResponse = serde_json::from_slice(json)?;
+
+        let started = Instant::now();
+        write_packet(&mut stream, 0x01, &0i64.to_be_bytes()).await?;
+        read_packet(&mut stream).await?;
+        let latency = started.elapsed();
+
+        let (version, protocol) = response.version.map(|v| (v.name, v.protocol)).unwrap_or_default();
+        let (online, max, players) = response.players.map(|p| (p.online, p.max, p.sample.into_iter().map(|s| s.name).collect())).unwrap_or_default();
+        Ok(ServerStatus { motd: chat_to_text(&response.description), version, protocol, online, max, players, favicon: response.favicon.as_deref().and_then(decode_favicon).map(|png| favicon_url(&png)), latency })
+    }
+
+    /// The `0xFE 0x01` ping understood by 1.4-1.6 servers. Beta 1.8-1.3 servers answer it in their older format.
+    async fn legacy_status(server: &str, port: u16) -> Result<ServerStatus, Box<dyn Error + Send + Sync>> {
+        let started = Instant::now();
+        let mut stream = TcpStream::connect((server, port)).await?;
+        stream.write_all(&[0xFE, 0x01]).await?;
+        if stream.read_u8().await? != 0xFF {
+            return Err("Not a legacy ping response".into());
+        }
+        let length = stream.read_u16().await? as usize;
+        let mut raw = vec![0; length * 2];
+        stream.read_exact(&mut raw).await?;
+        let latency = started.elapsed();
+
+        let text = String::from_utf16_lossy(&raw.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect::<Vec<u16>>());
+        let (motd, version, protocol, online, max) = match text.strip_prefix("\u{a7}1\0") {
+            Some(fields) => {
+                let fields = fields.split('\0').collect::<Vec<&str>>();
+                let field = |i: usize| fields.get(i).copied().unwrap_or_default();
+                (field(2).to_string(), field(1).to_string(), field(0).parse().unwrap_or_default(), field(3).parse().unwrap_or_default(), field(4).parse().unwrap_or_default())
+            }
+            None => {
+                let mut fields = text.rsplitn(3, '\u{a7}');
+                let max = fields.next().unwrap_or_default().parse().unwrap_or_default();
+                let online = fields.next().unwrap_or_default().parse().unwrap_or_default();
+                (fields.next().unwrap_or_default().to_string(), String::new(), 0, online, max)
+            }
+        };
+        Ok(ServerStatus { motd, version, protocol, online, max, players: Vec::new(), favicon: None, latency })
+    }
+
+    /// Flattens a chat component (plain string, object with `text`/`extra`, or array) to plain text.
+    fn chat_to_text(component: &Value) -> String {
+        match component {
+            Value::String(text) => text.clone(),
+            Value::Array(parts) => parts.iter().map(chat_to_text).collect(),
+            Value::Object(object) => {
+                let mut text = object.get("text").map(chat_to_text).unwrap_or_default();
+                if let Some(extra) = object.get("extra") {
+                    text.push_str(&chat_to_text(extra));
+                }
+                text
+            }
+            _ => String::new()
+        }
+    }
+}
+
+pub mod assets {
+    use std::{collections::HashMap, error::Error, path::PathBuf};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize)]
+    pub struct SingleAsset {
+        pub hash: String,
+        pub sha1: Option<String>,
+        pub size: Option<u64>
+    }
+
+    impl SingleAsset {
+        pub fn to_url(&self) -> String {
+            ["https://resources.download.minecraft.net/", &self.hash[..2], "/", &self.hash].concat()
+        }
+
+        pub fn to_path(&self) -> PathBuf {
+            let mut p = PathBuf::new();
+            p.push("objects");
+            p.push(&self.hash[..2]);
+            p.push(&self.hash);
+            p
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Assets {
+        pub objects: HashMap<String, SingleAsset>,
+        /// Pre-1.7 indexes: objects are expected under `assets/virtual/<id>/<name>`.
+        #[serde(rename = "virtual", default)]
+        pub is_virtual: bool,
+        /// Pre-1.6 indexes: objects are expected under `<game_dir>/resources/<name>`.
+        #[serde(default)]
+        pub map_to_resources: bool
+    }
+
+    pub async fn fetch_assets_list(url: &str) -> Result<Assets, Box<dyn Error + Send + Sync>> {
+        let mut r = surf::get(url).await?;
+        let resp = r.body_bytes().await.unwrap();
+        let resp: Assets = serde_json::from_slice(&resp)?;
+        Ok(resp)
+    }
+}
+
+/// Local HTTP server standing in for the auth services in tests.
+#[cfg(test)]
+mod stub {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    pub struct Request {
+        pub path: String,
+        /// Request line and headers as sent.
+        pub headers: String,
+        pub body: String
+    }
+
+    /// Answers every request on a local port with the status line and JSON `respond` picks for it, and reports
+    /// each answered request. Returns the server's base URL.
+    pub async fn serve(mut respond: impl FnMut(&Request) -> (&'static str, String) + Send + 'static) -> (String, mpsc::UnboundedReceiver<Request>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = Vec::new();
+                let head_end = loop {
+                    let mut chunk = [0u8; 4096];
+                    let n = stream.read(&mut chunk).await.unwrap();
+                    buf.extend_from_slice(&chunk[..n]);
+                    if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break i + 4;
+                    }
+                };
+                let headers = String::from_utf8_lossy(&buf[..head_end]).to_string();
+                let length: usize = headers.lines()
+                    .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse().unwrap()))
+                    .unwrap_or(0);
+                let mut body = buf[head_end..].to_vec();
+                let mut rest = vec![0u8; length - body.len()];
+                stream.read_exact(&mut rest).await.unwrap();
+                body.extend(rest);
+
+                let request = Request {
+                    path: headers.split_whitespace().nth(1).unwrap().to_string(),
+                    headers: headers.clone(),
+                    body: String::from_utf8(body).unwrap()
+                };
+                let (status, reply) = respond(&request);
+                let response = format!("HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, reply.len(), reply);
+                stream.write_all(response.as_bytes()).await.unwrap();
+                tx.send(request).unwrap();
+            }
+        });
+        (url, rx)
+    }
+}
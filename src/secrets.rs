@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::Rng;
+
+const KEYRING_SERVICE: &str = "xcraft";
+const FILE_MAGIC: &[u8; 4] = b"XCS1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Where account secrets live, keyed by `username@domain`.
+pub trait SecretStore: Send + Sync {
+    fn get(&self, account: &str) -> Result<Option<String>, Box<dyn Error + Send + Sync>>;
+    fn set(&mut self, account: &str, secret: &str) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
+
+/// The platform secret service: Keychain, Credential Manager or Secret Service.
+pub struct KeyringStore;
+
+impl KeyringStore {
+    /// Returns the store if the platform secret service can actually be reached.
+    pub fn open() -> Result<Self, Box<dyn Error + Send + Sync>> {
+        match off_runtime(|| keyring::Entry::new(KEYRING_SERVICE, "probe")?.get_password()) {
+            Ok(_) | Err(keyring::Error::NoEntry) => Ok(Self),
+            Err(e) => Err(e.into())
+        }
+    }
+}
+
+impl SecretStore for KeyringStore {
+    fn get(&self, account: &str) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        let account = account.to_string();
+        match off_runtime(move || keyring::Entry::new(KEYRING_SERVICE, &account)?.get_password()) {
+            Ok(secret) => Ok(Some(secret)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(e.into())
+        }
+    }
+
+    fn set(&mut self, account: &str, secret: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (account, secret) = (account.to_string(), secret.to_string());
+        Ok(off_runtime(move || keyring::Entry::new(KEYRING_SERVICE, &account)?.set_password(&secret))?)
+    }
+}
+
+/// On Linux keyring talks to the Secret Service through its own tokio runtime, which panics when started
+/// from inside ours, so every keyring call runs on a thread of its own.
+fn off_runtime<T: Send + 'static>(f: impl FnOnce() -> keyring::Result<T> + Send + 'static) -> keyring::Result<T> {
+    std::thread::spawn(f).join().unwrap_or_else(|_| Err(keyring::Error::PlatformFailure("Keyring thread panicked".into())))
+}
+
+/// Secrets kept in a file encrypted with a key derived from a user passphrase, for portable installs.
+/// Layout: magic, Argon2id salt, ChaCha20-Poly1305 nonce, then the sealed JSON map of accounts.
+pub struct EncryptedFileStore {
+    path: PathBuf,
+    salt: [u8; SALT_LEN],
+    key: Key,
+    entries: HashMap<String, String>
+}
+
+impl EncryptedFileStore {
+    /// Decrypts the file at `path`, or starts an empty one if it doesn't exist yet.
+    pub fn open(path: PathBuf, passphrase: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        if !path.exists() {
+            let mut salt = [0u8; SALT_LEN];
+            rand::rng().fill(&mut salt);
+            let key = derive_key(passphrase, &salt)?;
+            return Ok(Self { path, salt, key, entries: HashMap::new() });
+        }
+
+        let data = std::fs::read(&path)?;
+        if data.len() < FILE_MAGIC.len() + SALT_LEN + NONCE_LEN || !data.starts_with(FILE_MAGIC) {
+            return Err("Credentials file is corrupted".into());
+        }
+        let (salt, rest) = data[FILE_MAGIC.len()..].split_at(SALT_LEN);
+        let (nonce, sealed) = rest.split_at(NONCE_LEN);
+        let salt: [u8; SALT_LEN] = salt.try_into()?;
+        let nonce: [u8; NONCE_LEN] = nonce.try_into()?;
+        let key = derive_key(passphrase, &salt)?;
+        let plain = ChaCha20Poly1305::new(&key).decrypt(&Nonce::from(nonce), sealed)
+            .map_err(|_| "Wrong passphrase")?;
+        Ok(Self { path, salt, key, entries: serde_json::from_slice(&plain)? })
+    }
+
+    fn save(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::rng().fill(&mut nonce);
+        let sealed = ChaCha20Poly1305::new(&self.key).encrypt(&Nonce::from(nonce), serde_json::to_vec(&self.entries)?.as_slice())
+            .map_err(|_| "Could not encrypt credentials")?;
+        std::fs::write(&self.path, [FILE_MAGIC.as_slice(), &self.salt, &nonce, &sealed].concat())?;
+        Ok(())
+    }
+}
+
+impl SecretStore for EncryptedFileStore {
+    fn get(&self, account: &str) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        Ok(self.entries.get(account).cloned())
+    }
+
+    fn set(&mut self, account: &str, secret: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.entries.insert(account.to_string(), secret.to_string());
+        self.save()
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key, Box<dyn Error + Send + Sync>> {
+    let mut key = Key::default();
+    Argon2::default().hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| e.to_string())?;
+    Ok(key)
+}
@@ -1,10 +1,17 @@
+use std::collections::HashSet;
 use std::error::Error;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use base64::prelude::BASE64_STANDARD;
 use base64::Engine;
-use futures::AsyncReadExt;
+use futures::{AsyncReadExt, StreamExt};
 use rand::{distr::Alphanumeric, Rng};
-use tokio::{fs::File, io::AsyncWriteExt};
+use sha1::{Digest, Sha1};
+use surf::StatusCode;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
 use tokio::sync::mpsc::UnboundedSender;
 
 pub fn random_string(len: usize) -> String {
@@ -15,6 +22,98 @@ pub fn random_string(len: usize) -> String {
         .collect()
 }
 
+/// UUID vanilla gives offline players: a version 3 UUID of `OfflinePlayer:<name>`, as 32 hex digits.
+pub fn offline_uuid(name: &str) -> String {
+    let mut hash: [u8; 16] = md5::Md5::digest(["OfflinePlayer:", name].concat()).into();
+    hash[6] = (hash[6] & 0x0f) | 0x30;
+    hash[8] = (hash[8] & 0x3f) | 0x80;
+    hash.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn unix_time() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+#[cfg(unix)]
+pub fn set_executable(file_path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut permissions = std::fs::metadata(file_path)?.permissions();
+    permissions.set_mode(permissions.mode() | 0o755);
+    std::fs::set_permissions(file_path, permissions)
+}
+
+#[cfg(not(unix))]
+pub fn set_executable(_file_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Opens a URL or a file with the default handler of the host OS.
+pub fn open_in_shell(target: &str) -> std::io::Result<()> {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = std::process::Command::new("cmd");
+        cmd.args(["/C", "start", "", target]);
+        cmd
+    } else if cfg!(target_os = "macos") {
+        let mut cmd = std::process::Command::new("open");
+        cmd.arg(target);
+        cmd
+    } else {
+        let mut cmd = std::process::Command::new("xdg-open");
+        cmd.arg(target);
+        cmd
+    };
+    cmd.spawn()?;
+    Ok(())
+}
+
+/// Recursively copies the contents of `from` into `to`, overwriting files that already exist.
+pub fn copy_dir_all(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir_all(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Accepts a relative path from a pack manifest only if it stays inside the directory it is joined to.
+pub fn safe_relative_path(path: &str) -> Option<PathBuf> {
+    let path = PathBuf::from(path);
+    let is_safe = path.components().all(|c| matches!(c, std::path::Component::Normal(_)));
+    (is_safe && path.components().next().is_some()).then_some(path)
+}
+
+/// Every file under `dir`, recursively.
+pub fn list_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            files.extend(list_files(&entry.path())?);
+        } else {
+            files.push(entry.path());
+        }
+    }
+    Ok(files)
+}
+
+/// Removes empty directories below `dir`, keeping `dir` itself.
+pub fn remove_empty_dirs(dir: &Path) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            remove_empty_dirs(&entry.path())?;
+            let _ = std::fs::remove_dir(entry.path());
+        }
+    }
+    Ok(())
+}
+
 pub async fn get_image(url: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
     let bytes = surf::get(url).recv_bytes().await?;
     let base64_string = BASE64_STANDARD.encode(&bytes);
@@ -28,35 +127,247 @@ pub async fn simple_download(url: &str, file_path: &str) -> Result<(), Box<dyn E
     Ok(())
 }
 
-pub async fn download_file(url: &str, file_path: &str, sender: UnboundedSender<(usize, String)>, status: &str, join: bool) -> Result<(), Box<dyn std::error::Error>> {
-    let url = url.to_string();
-    let file_path = file_path.to_string();
-    let status = status.to_string();
-    let g = tokio::spawn( async move {
-        if let Ok(mut res) = surf::get(url).await {
-            let mut downloaded = 0;
-            let mut buf = vec![0; 8192]; // Buffer for reading chunks
-            
-            let mut file = File::create(file_path).await.unwrap();
-
-            let mut r= res.take_body().into_reader();
-            while let Ok(n) = r.read(&mut buf).await {
-                if n == 0 {
-                    break;
+const DOWNLOAD_ATTEMPTS: usize = 4;
+const RETRY_BASE_DELAY_MS: u64 = 500;
+
+fn hash_file_into(file_path: &Path, hasher: &mut Sha1) -> std::io::Result<()> {
+    let mut file = std::fs::File::open(file_path)?;
+    let mut buf = vec![0; 8192];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(())
+}
+
+pub fn file_sha1(file_path: &Path) -> std::io::Result<String> {
+    let mut hasher = Sha1::new();
+    hash_file_into(file_path, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Returns true when the file exists and matches the expected size and SHA-1 (when they are known).
+pub fn is_file_valid(file_path: &Path, sha1: Option<&str>, size: Option<u64>) -> bool {
+    let Ok(metadata) = std::fs::metadata(file_path) else { return false; };
+    if !metadata.is_file() {
+        return false;
+    }
+    if size.is_some_and(|size| metadata.len() != size) {
+        return false;
+    }
+    match sha1 {
+        Some(sha1) => file_sha1(file_path).is_ok_and(|h| h.eq_ignore_ascii_case(sha1)),
+        None => true
+    }
+}
+
+fn part_path(file_path: &Path) -> PathBuf {
+    let mut name = file_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    file_path.with_file_name(name)
+}
+
+/// Downloads into `<file>.part`, resuming it with a `Range` request when the server supports it,
+/// and moves it into place only after the size and SHA-1 check out.
+async fn fetch_to_file(url: &str, file_path: &Path, sha1: Option<&str>, size: Option<u64>) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    let part_path = part_path(file_path);
+    let mut offset = tokio::fs::metadata(&part_path).await.map(|m| m.len()).unwrap_or(0);
+    if size.is_some_and(|size| offset > size) {
+        tokio::fs::remove_file(&part_path).await?;
+        offset = 0;
+    }
+
+    let mut request = surf::get(url);
+    if offset > 0 {
+        request = request.header("Range", format!("bytes={}-", offset));
+    }
+    let mut res = request.await?;
+
+    let mut hasher = Sha1::new();
+    let mut read_body = true;
+    let mut file = match res.status() {
+        StatusCode::PartialContent if offset > 0 => {
+            hash_file_into(&part_path, &mut hasher)?;
+            OpenOptions::new().append(true).open(&part_path).await?
+        }
+        // Nothing left past what we already have, so the part file is either complete or garbage
+        StatusCode::RequestedRangeNotSatisfiable if offset > 0 => {
+            hash_file_into(&part_path, &mut hasher)?;
+            read_body = false;
+            OpenOptions::new().append(true).open(&part_path).await?
+        }
+        status if status.is_success() => {
+            offset = 0;
+            File::create(&part_path).await?
+        }
+        status => return Err(format!("{} returned {}", url, status).into())
+    };
+
+    let mut downloaded = offset as usize;
+    let mut buf = vec![0; 8192]; // Buffer for reading chunks
+
+    let mut r = res.take_body().into_reader();
+    if read_body {
+        loop {
+            let n = r.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            downloaded += n;
+            hasher.update(&buf[..n]);
+            file.write_all(&buf[..n]).await?;
+        }
+    }
+    file.flush().await?;
+    drop(file);
+
+    if let Some(size) = size.filter(|size| *size != downloaded as u64) {
+        tokio::fs::remove_file(&part_path).await?;
+        return Err(format!("{}: expected {} bytes, got {}", url, size, downloaded).into());
+    }
+    if let Some(sha1) = sha1 {
+        let actual = format!("{:x}", hasher.finalize());
+        if !actual.eq_ignore_ascii_case(sha1) {
+            tokio::fs::remove_file(&part_path).await?;
+            return Err(format!("{}: expected sha1 {}, got {}", url, sha1, actual).into());
+        }
+    }
+    tokio::fs::rename(&part_path, file_path).await?;
+    Ok(downloaded)
+}
+
+pub struct DownloadTask {
+    pub url: String,
+    pub path: PathBuf,
+    pub sha1: Option<String>,
+    pub size: Option<u64>,
+    pub status: String,
+    pub executable: bool
+}
+
+pub struct DownloadFailure {
+    pub url: String,
+    pub path: PathBuf,
+    pub error: String
+}
+
+#[derive(Default)]
+pub struct DownloadReport {
+    pub downloaded: usize,
+    pub failed: Vec<DownloadFailure>
+}
+
+impl DownloadReport {
+    /// Final message for the loading screen: `_` on success, `_` followed by the error text otherwise.
+    pub fn final_status(&self) -> String {
+        if self.failed.is_empty() {
+            return "_".to_string();
+        }
+        let names = self.failed.iter()
+            .map(|f| f.path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or(f.url.clone()))
+            .collect::<Vec<String>>();
+        format!("_Failed to download {} file(s): {}", self.failed.len(), names.join(", "))
+    }
+}
+
+pub struct DownloadQueue {
+    tasks: Vec<DownloadTask>,
+    queued: HashSet<PathBuf>,
+    parallelism: usize,
+    attempts: usize
+}
+
+impl DownloadQueue {
+    pub fn new(parallelism: usize) -> Self {
+        Self { tasks: Vec::new(), queued: HashSet::new(), parallelism: parallelism.max(1), attempts: DOWNLOAD_ATTEMPTS }
+    }
+
+    pub fn push(&mut self, url: &str, path: PathBuf, sha1: Option<&str>, size: Option<u64>, status: &str) {
+        if !self.queued.insert(path.clone()) {
+            return;
+        }
+        self.tasks.push(DownloadTask { url: url.to_string(), path, sha1: sha1.map(|s| s.to_string()), size, status: status.to_string(), executable: false });
+    }
+
+    /// Same as `push_if_invalid`, but the file gets the executable bit once it's in place.
+    pub fn push_executable_if_invalid(&mut self, url: &str, path: PathBuf, sha1: Option<&str>, size: Option<u64>, status: &str) {
+        if is_file_valid(&path, sha1, size) {
+            let _ = set_executable(&path);
+            return;
+        }
+        self.push(url, path.clone(), sha1, size, status);
+        if let Some(task) = self.tasks.iter_mut().rev().find(|t| t.path == path) {
+            task.executable = true;
+        }
+    }
+
+    /// Queues the file only when it is missing or doesn't match the expected hash and size.
+    pub fn push_if_invalid(&mut self, url: &str, path: PathBuf, sha1: Option<&str>, size: Option<u64>, status: &str) {
+        if !is_file_valid(&path, sha1, size) {
+            self.push(url, path, sha1, size, status);
+        }
+    }
+
+    pub async fn run(self, sender: &UnboundedSender<(u8, String)>) -> DownloadReport {
+        let total_size: u64 = self.tasks.iter().map(|t| t.size.unwrap_or(0)).sum();
+        let total_cnt = self.tasks.len();
+        let attempts = self.attempts;
+
+        let mut results = futures::stream::iter(self.tasks.into_iter().map(|task| async move {
+            let result = download_with_retries(&task, attempts).await;
+            (task, result)
+        })).buffer_unordered(self.parallelism);
+
+        let mut report = DownloadReport::default();
+        let mut current_size = 0;
+        let mut current_cnt = 0;
+        while let Some((task, result)) = results.next().await {
+            current_cnt += 1;
+            current_size += task.size.unwrap_or(0);
+            match result {
+                Ok(()) => report.downloaded += 1,
+                Err(e) => {
+                    println!("Failed to download {}: {}", task.url, e);
+                    report.failed.push(DownloadFailure { url: task.url, path: task.path, error: e.to_string() });
                 }
-                downloaded += n;
-                
-                let _ = file.write(&buf[..n]).await;
             }
-            let _ = sender.send((downloaded, status.clone()));
-        } else {
-            let _ = sender.send((0, status.clone()));
+            let percent = (current_size * 100).checked_div(total_size).unwrap_or((current_cnt * 100 / total_cnt) as u64);
+            let _ = sender.send((percent.min(100) as u8, task.status));
+        }
+        report
+    }
+}
+
+async fn download_with_retries(task: &DownloadTask, attempts: usize) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if let Some(parent) = task.path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut attempt = 0;
+    loop {
+        match fetch_to_file(&task.url, &task.path, task.sha1.as_deref(), task.size).await {
+            Ok(_) if task.executable => return Ok(set_executable(&task.path)?),
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                attempt += 1;
+                if attempt >= attempts {
+                    return Err(e);
+                }
+                tokio::time::sleep(Duration::from_millis(RETRY_BASE_DELAY_MS << (attempt - 1))).await;
+            }
         }
-    });
-    if join {
-        let _ = g.await;
     }
-    Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    #[test]
+    fn offline_uuid_matches_vanilla() {
+        assert_eq!(offline_uuid("Notch"), "b50ad385829d3141a2167e7d7539ba7f");
+        assert_ne!(offline_uuid("Notch"), offline_uuid("notch"));
+    }
+}